@@ -0,0 +1,7 @@
+fn main() {
+  println!("cargo:rerun-if-changed=src/shim/cxxstring.cpp");
+  // the CxxString tests check the libstdc++ layout against real strings, only when asked for
+  if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("linux") && std::env::var_os("CARGO_FEATURE_SHIM_TESTS").is_some() {
+    cc::Build::new().cpp(true).file("src/shim/cxxstring.cpp").compile("cxxstring_shim");
+  }
+}
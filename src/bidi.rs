@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use unicode_bidi::BidiInfo;
+
+use crate::cache::Cache;
+use crate::config::CONFIG;
+use crate::dictionary::Translation;
+use crate::encoding::Encoding;
+
+#[static_init::dynamic(lazy)]
+static BIDI: Bidi = Bidi::new(&CONFIG.encoding);
+
+// the translation in the order DF has to draw it, None when there is nothing right-to-left in it
+pub fn visual(logical: &[u8]) -> Option<Arc<Translation>> {
+  BIDI.visual(logical)
+}
+
+// DF draws strings glyph by glyph from the left, so right-to-left text has to be handed over in visual order.
+// The glyphs are decoded through the utf map of the encoding, reordered by the Unicode bidirectional algorithm
+// and put back as the same bytes, only mirrored brackets are encoded again.
+pub struct Bidi {
+  chars: [char; 256],
+  bytes: HashMap<char, u8>,
+  cache: Cache<Option<Arc<Translation>>>,
+}
+
+impl Bidi {
+  pub fn new(encoding: &Encoding) -> Self {
+    let mut chars: [char; 256] = core::array::from_fn(|i| match i < 128 {
+      true => i as u8 as char,
+      false => char::REPLACEMENT_CHARACTER,
+    });
+    let mut bytes = HashMap::new();
+    let mut utf: Vec<(u32, u8)> = encoding.utf.iter().map(|(code, byte)| (*code, *byte)).collect();
+    // several symbols can share a glyph, the lowest one decodes it
+    utf.sort_unstable_by(|a, b| b.cmp(a));
+    for (code, byte) in utf {
+      let Some(c) = char::from_u32(code) else {
+        continue;
+      };
+      if byte >= 128 {
+        chars[byte as usize] = c;
+      }
+      bytes.insert(c, byte);
+    }
+    Self {
+      chars,
+      bytes,
+      cache: Cache::default(),
+    }
+  }
+
+  pub fn visual(&self, logical: &[u8]) -> Option<Arc<Translation>> {
+    // plain ASCII is never right-to-left
+    if logical.is_ascii() {
+      return None;
+    }
+    self.cache.get_or_insert_with(logical, || {
+      self.reorder(logical).map(|visual| Arc::new(Translation::new(&visual)))
+    })
+  }
+
+  fn reorder(&self, logical: &[u8]) -> Option<Vec<u8>> {
+    let text: String = logical.iter().map(|byte| self.chars[*byte as usize]).collect();
+    let info = BidiInfo::new(&text, None);
+    if !info.has_rtl() {
+      return None;
+    }
+    // the byte each char of text was decoded from, by its offset in text
+    let mut source = vec![0u8; text.len()];
+    for ((offset, _), byte) in text.char_indices().zip(logical) {
+      source[offset] = *byte;
+    }
+    let mut visual = Vec::with_capacity(logical.len());
+    for paragraph in &info.paragraphs {
+      let (levels, runs) = info.visual_runs(paragraph, paragraph.range.clone());
+      for run in runs {
+        let chars = text[run.clone()].char_indices().map(|(i, c)| (run.start + i, c));
+        match levels[run.start].is_rtl() {
+          true => visual.extend(chars.rev().map(|(i, c)| self.mirror(c).unwrap_or(source[i]))),
+          false => visual.extend(chars.map(|(i, _)| source[i])),
+        }
+      }
+    }
+    Some(visual)
+  }
+
+  // brackets point the other way inside right-to-left runs
+  fn mirror(&self, c: char) -> Option<u8> {
+    let mirrored = match c {
+      '(' => ')',
+      ')' => '(',
+      '[' => ']',
+      ']' => '[',
+      '{' => '}',
+      '}' => '{',
+      '<' => '>',
+      '>' => '<',
+      '«' => '»',
+      '»' => '«',
+      _ => return None,
+    };
+    match mirrored.is_ascii() {
+      true => Some(mirrored as u8),
+      false => self.bytes.get(&mirrored).copied(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // shalom in a made up Hebrew encoding, glyphs 0x80-0x83
+  const SHALOM: [u8; 4] = [0x80, 0x81, 0x82, 0x83];
+
+  fn bidi() -> Bidi {
+    let encoding = Encoding {
+      utf: HashMap::from([
+        (0x05e9, 0x80),
+        (0x05dc, 0x81),
+        (0x05d5, 0x82),
+        (0x05dd, 0x83),
+        (0xab, 0xae),
+        (0xbb, 0xaf),
+      ]),
+      ..Default::default()
+    };
+    Bidi::new(&encoding)
+  }
+
+  fn reordered(bidi: &Bidi, logical: &[u8]) -> Option<Vec<u8>> {
+    bidi.visual(logical).map(|visual| visual.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn right_to_left_text_is_reversed() {
+    let bidi = bidi();
+    assert_eq!(reordered(&bidi, &SHALOM), Some(vec![0x83, 0x82, 0x81, 0x80]));
+    assert_eq!(reordered(&bidi, b"Urist McMiner"), None);
+    // glyphs the encoding does not know are left alone
+    assert_eq!(reordered(&bidi, &[b'a', 0xf0, b'b']), None);
+  }
+
+  #[test]
+  fn numbers_and_latin_names_keep_their_order() {
+    let bidi = bidi();
+    let logical = [&SHALOM[..], b" Urist 42"].concat();
+    assert_eq!(
+      reordered(&bidi, &logical),
+      Some([b"Urist 42 ", &[0x83, 0x82, 0x81, 0x80][..]].concat())
+    );
+    // a left-to-right string keeps the number next to the Hebrew word it belongs to
+    let logical = [b"Urist ", &SHALOM[..], b" 42"].concat();
+    assert_eq!(
+      reordered(&bidi, &logical),
+      Some([b"Urist 42 ", &[0x83, 0x82, 0x81, 0x80][..]].concat())
+    );
+  }
+
+  #[test]
+  fn brackets_are_mirrored() {
+    let bidi = bidi();
+    let logical = [b"(", &SHALOM[..], b")"].concat();
+    assert_eq!(
+      reordered(&bidi, &logical),
+      Some([b"(", &[0x83, 0x82, 0x81, 0x80][..], b")"].concat())
+    );
+    let logical = [&[0xae][..], &SHALOM[..], &[0xaf][..]].concat();
+    assert_eq!(
+      reordered(&bidi, &logical),
+      Some(vec![0xae, 0x83, 0x82, 0x81, 0x80, 0xaf])
+    );
+  }
+
+  #[test]
+  fn results_are_cached() {
+    let bidi = bidi();
+    let first = bidi.visual(&SHALOM).unwrap();
+    assert!(Arc::ptr_eq(&first, &bidi.visual(&SHALOM).unwrap()));
+    assert_eq!(bidi.cache.len(), 1);
+  }
+}
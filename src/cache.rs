@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const LIMIT: usize = 4096;
+
+// Results for strings that are looked up again every frame, misses included. Hooks are called from the render
+// thread and from DF's worker threads, so a caller that finds the cache busy works the result out without it
+// instead of waiting for the lock. Once full, the cache starts over.
+pub struct Cache<V> {
+  map: Mutex<HashMap<Vec<u8>, V>>,
+}
+
+impl<V: Clone> Cache<V> {
+  pub fn get_or_insert_with(&self, key: &[u8], f: impl FnOnce() -> V) -> V {
+    let Ok(mut map) = self.map.try_lock() else {
+      return f();
+    };
+    if let Some(value) = map.get(key) {
+      return value.clone();
+    }
+    let value = f();
+    if map.len() >= LIMIT {
+      map.clear();
+    }
+    map.insert(key.to_vec(), value.clone());
+    value
+  }
+
+  #[cfg(test)]
+  pub fn len(&self) -> usize {
+    self.map.lock().map_or(0, |map| map.len())
+  }
+}
+
+impl<V> Default for Cache<V> {
+  fn default() -> Self {
+    Self {
+      map: Mutex::new(HashMap::new()),
+    }
+  }
+}
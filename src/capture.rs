@@ -0,0 +1,254 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::CONFIG;
+use crate::csv;
+use crate::dictionary::DICTIONARY;
+use crate::paths::PATHS;
+
+// new strings are no longer taken once this many are waiting, e.g. when a hook sees generated text
+const LIMIT: usize = 10000;
+
+#[static_init::dynamic(lazy)]
+static CAPTURE: Capture = Capture::new();
+
+#[static_init::dynamic]
+static mut KILL: bool = false;
+
+// the files are only rewritten once the previous session was merged, or its rows would be lost
+#[static_init::dynamic]
+static mut INSTALLED: bool = false;
+
+struct Miss {
+  first_seen: String,
+  hits: u64,
+  hooks: Vec<String>,
+}
+
+// Strings the hooks saw but the dictionary did not know. untranslated.csv holds "key","" rows that
+// Dictionary::load reads as they are, the counters go to untranslated.stats.csv:
+// "key","first seen","hits","hook|hook"
+pub struct Capture {
+  misses: Mutex<HashMap<Vec<u8>, Miss>>,
+}
+
+impl Capture {
+  fn new() -> Self {
+    Self {
+      misses: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // called from the render thread, a miss is dropped rather than waited for when the writer holds the lock;
+  // single characters are skipped like the string hooks skip them, an empty key is no dictionary row
+  pub fn record(&self, hook: &str, key: &[u8]) {
+    if key.len() < 2 {
+      return;
+    }
+    let Ok(mut misses) = self.misses.try_lock() else {
+      return;
+    };
+    if let Some(miss) = misses.get_mut(key) {
+      miss.hits += 1;
+      if !miss.hooks.iter().any(|h| h == hook) {
+        miss.hooks.push(String::from(hook));
+      }
+      return;
+    }
+    if misses.len() >= LIMIT {
+      return;
+    }
+    drop(misses);
+    let first_seen = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let Ok(mut misses) = self.misses.try_lock() else {
+      return;
+    };
+    let miss = misses.entry(key.to_vec()).or_insert_with(|| Miss {
+      first_seen,
+      hits: 0,
+      hooks: Vec::new(),
+    });
+    miss.hits += 1;
+    if !miss.hooks.iter().any(|h| h == hook) {
+      miss.hooks.push(String::from(hook));
+    }
+  }
+
+  // keeps counters from previous sessions, rows that got translated since then are dropped;
+  // known tells whether a key is translated for a hook
+  pub fn merge(&self, data: &[u8], known: impl Fn(&[u8], &str) -> bool) {
+    let Ok(mut misses) = self.misses.lock() else {
+      return;
+    };
+    for record in csv::Reader::new(data).flatten() {
+      let [key, first_seen, hits, hooks] = record.fields.as_slice() else {
+        continue;
+      };
+      let hooks = String::from_utf8_lossy(hooks);
+      let hooks: Vec<&str> = hooks.split('|').filter(|h| !h.is_empty()).collect();
+      if !hooks.is_empty() && hooks.iter().all(|hook| known(key, hook)) {
+        continue;
+      }
+      let hits = String::from_utf8_lossy(hits).parse::<u64>().unwrap_or(0);
+      let miss = misses.entry(key.clone()).or_insert_with(|| Miss {
+        first_seen: String::new(),
+        hits: 0,
+        hooks: Vec::new(),
+      });
+      miss.first_seen = String::from_utf8_lossy(first_seen).into_owned();
+      miss.hits += hits;
+      for hook in hooks {
+        if !miss.hooks.iter().any(|h| h == hook) {
+          miss.hooks.push(String::from(hook));
+        }
+      }
+    }
+  }
+
+  // strings translated by a reloaded dictionary for every hook that saw them are no longer missing
+  pub fn forget(&self, known: impl Fn(&[u8], &str) -> bool) {
+    if let Ok(mut misses) = self.misses.lock() {
+      misses.retain(|key, miss| !miss.hooks.iter().all(|hook| known(key, hook)));
+    }
+  }
+
+  fn rows<T>(&self, row: impl Fn(&[u8], &Miss) -> T) -> Vec<T> {
+    let Ok(misses) = self.misses.lock() else {
+      return Vec::new();
+    };
+    let mut rows: Vec<(&Vec<u8>, &Miss)> = misses.iter().collect();
+    rows.sort_by(|a, b| b.1.hits.cmp(&a.1.hits).then_with(|| a.0.cmp(b.0)));
+    rows.into_iter().map(|(key, miss)| row(key, miss)).collect()
+  }
+
+  pub fn render(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    for key in self.rows(|key, _| key.to_vec()) {
+      csv::write_record(&mut out, &[&key, b""]);
+    }
+    out
+  }
+
+  pub fn render_stats(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    let rows = self.rows(|key, miss| {
+      [
+        key.to_vec(),
+        miss.first_seen.as_bytes().to_vec(),
+        miss.hits.to_string().into_bytes(),
+        miss.hooks.join("|").into_bytes(),
+      ]
+    });
+    for [key, first_seen, hits, hooks] in rows {
+      csv::write_record(&mut out, &[&key, &first_seen, &hits, &hooks]);
+    }
+    out
+  }
+
+  pub fn write(&self, path: &str, stats: &str) -> Result<()> {
+    for (path, data) in [(path, self.render()), (stats, self.render_stats())] {
+      let tmp = format!("{path}.tmp");
+      std::fs::write(&tmp, data)?;
+      std::fs::rename(&tmp, path)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn record(hook: &str, key: &[u8]) {
+  if CONFIG.settings.capture_untranslated {
+    CAPTURE.record(hook, key);
+  }
+}
+
+fn flush() {
+  let dictionary = DICTIONARY.load();
+  CAPTURE.forget(|key, hook| dictionary.get(key, hook).is_some());
+  if let Err(err) = CAPTURE.write(&PATHS.untranslated, &PATHS.untranslated_stats) {
+    log::error!("unable to write untranslated strings {}, {err:?}", PATHS.untranslated);
+  }
+}
+
+pub fn install() {
+  if let Ok(data) = std::fs::read(&PATHS.untranslated_stats) {
+    let dictionary = DICTIONARY.load();
+    CAPTURE.merge(&data, |key, hook| dictionary.get(key, hook).is_some());
+  }
+  *INSTALLED.write() = true;
+  log::info!("capturing untranslated strings to {}", PATHS.untranslated);
+  std::thread::spawn(move || {
+    let interval = std::time::Duration::from_secs(CONFIG.settings.capture_interval.max(1));
+    let mut last = std::time::Instant::now();
+    while !*KILL.read() {
+      std::thread::sleep(std::time::Duration::from_millis(250));
+      if last.elapsed() >= interval {
+        flush();
+        last = std::time::Instant::now();
+      }
+    }
+  });
+}
+
+pub fn uninstall() {
+  *KILL.write() = true;
+  if *INSTALLED.read() {
+    flush();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn misses_are_deduplicated() {
+    let capture = Capture::new();
+    capture.record("addst", b"Stone");
+    capture.record("addst", b"Stone");
+    capture.record("std_string_ctor", b"Stone");
+    capture.record("addst_top", b"Bar");
+
+    let rows: Vec<Vec<Vec<u8>>> = csv::Reader::new(&capture.render()).map(|r| r.unwrap().fields).collect();
+    assert_eq!(rows, vec![vec![b"Stone".to_vec(), b"".to_vec()], vec![b"Bar".to_vec(), b"".to_vec()]]);
+
+    let stats: Vec<Vec<Vec<u8>>> = csv::Reader::new(&capture.render_stats()).map(|r| r.unwrap().fields).collect();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0][0], b"Stone");
+    assert_eq!(stats[0][2], b"3");
+    assert_eq!(stats[0][3], b"addst|std_string_ctor");
+    assert_eq!(stats[1][0], b"Bar");
+  }
+
+  #[test]
+  fn short_keys_are_skipped_and_the_size_is_capped() {
+    let capture = Capture::new();
+    capture.record("addst", b"");
+    capture.record("string_copy_n", b"x");
+    assert!(capture.render().is_empty());
+
+    for i in 0..LIMIT + 10 {
+      capture.record("addst", format!("string {i}").as_bytes());
+    }
+    capture.record("addst_top", b"string 0");
+    let stats: Vec<Vec<Vec<u8>>> = csv::Reader::new(&capture.render_stats()).map(|r| r.unwrap().fields).collect();
+    assert_eq!(stats.len(), LIMIT);
+    assert_eq!(stats[0], [&b"string 0"[..], &stats[0][1], b"2", b"addst|addst_top"]);
+  }
+
+  #[test]
+  fn previous_session_is_merged() {
+    let capture = Capture::new();
+    capture.record("addst", b"Stone");
+    capture.merge(
+      b"\"Stone\",\"2026-01-01 10:00:00\",\"5\",\"addst_flag\"\n\"Bar\",\"2026-01-01 10:00:00\",\"2\",\"addst\"\n",
+      |key, _| key == b"Bar",
+    );
+
+    let rows: Vec<Vec<Vec<u8>>> = csv::Reader::new(&capture.render_stats()).map(|r| r.unwrap().fields).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][1], b"2026-01-01 10:00:00");
+    assert_eq!(rows[0][2], b"6");
+    assert_eq!(rows[0][3], b"addst|addst_flag");
+  }
+}
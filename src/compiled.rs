@@ -0,0 +1,361 @@
+use anyhow::{anyhow, Result};
+use memmap2::Mmap;
+use std::path::Path;
+
+use crate::dictionary::Translation;
+
+// Precompiled dictionary, memory-mapped and used in place.
+//
+// header:  magic, version u32, checksum u64, entries u32, templates u32, notes len u32, report len u32
+// index:   (key offset, key len, context offset, context len, value offset, value len) u32 each,
+//          entries sorted by key and context, then templates in load order
+// data:    keys and values; values are NUL-terminated and padded like dictionary::Translation,
+//          so hooks can hand pointers into the mapping to DF directly
+// trailer: the notes, one per line, then the diagnostics report of the build
+
+const MAGIC: &[u8; 8] = b"DFINTDIC";
+const VERSION: u32 = 3;
+const HEADER: usize = 8 + 4 + 8 + 4 + 4 + 4 + 4;
+const RECORD: usize = 24;
+
+pub struct Compiled {
+  mmap: Mmap,
+  entries: usize,
+  templates: usize,
+  notes: usize,
+  report: usize,
+}
+
+// what loading the layers logged and reported, repeated by starts that use the compiled file
+pub struct Notes<'a> {
+  pub lines: &'a [String],
+  pub report: &'a [u8],
+}
+
+fn u32_at(data: &[u8], offset: usize) -> usize {
+  u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+// FNV-1a over every layer that exists, in order, so adding, removing or editing any of them invalidates the file
+pub fn checksum(layers: &[String]) -> Result<u64> {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  let mut feed = |bytes: &[u8]| {
+    for &b in bytes {
+      hash ^= b as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+  };
+  for (i, path) in layers.iter().enumerate() {
+    let contents = match std::fs::read(path) {
+      Ok(contents) => contents,
+      Err(err) if i == 0 || err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+      Err(_) => continue,
+    };
+    feed(path.as_bytes());
+    feed(&(contents.len() as u64).to_le_bytes());
+    feed(&contents);
+  }
+  Ok(hash)
+}
+
+// The checksum is part of the file name: Windows refuses to replace a file that is mapped, and a running game
+// keeps its mapping of the previous build through reloads. dictionary.bin becomes dictionary.<checksum>.bin.
+pub fn versioned(path: &str, checksum: u64) -> String {
+  let path = Path::new(path);
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let name = match path.extension() {
+    Some(extension) => format!("{stem}.{checksum:016x}.{}", extension.to_string_lossy()),
+    None => format!("{stem}.{checksum:016x}"),
+  };
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// builds other than current; files still mapped cannot be removed on Windows and are left for the next start
+pub fn remove_stale(path: &str, current: &str) {
+  let path = Path::new(path);
+  let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+    return;
+  };
+  let stem = format!("{}.", stem.to_string_lossy());
+  let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+  let Ok(files) = std::fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else {
+    return;
+  };
+  for file in files.flatten() {
+    let name = file.file_name().to_string_lossy().into_owned();
+    if name.starts_with(&stem) && name.ends_with(&extension) && file.path() != Path::new(current) {
+      let _ = std::fs::remove_file(file.path());
+    }
+  }
+}
+
+// entries are (key, context, value), an empty context is the context-free entry
+pub fn build<'a>(
+  path: &str,
+  checksum: u64,
+  entries: impl Iterator<Item = (&'a [u8], &'a str, &'a [u8])>,
+  templates: &[(Vec<u8>, Vec<u8>)],
+  notes: Notes,
+) -> Result<()> {
+  let mut entries: Vec<(&[u8], &[u8], &[u8])> = entries.map(|(k, c, v)| (k, c.as_bytes(), v)).collect();
+  entries.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+  let records: Vec<(&[u8], &[u8], &[u8])> = entries
+    .into_iter()
+    .chain(templates.iter().map(|(k, v)| (k.as_slice(), b"".as_slice(), v.as_slice())))
+    .collect();
+
+  let mut index = Vec::with_capacity(records.len() * RECORD);
+  let mut data = Vec::new();
+  let base = HEADER + records.len() * RECORD;
+  for (key, context, value) in &records {
+    let key_offset = base + data.len();
+    data.extend_from_slice(key);
+    let context_offset = base + data.len();
+    data.extend_from_slice(context);
+    let value_offset = base + data.len();
+    data.extend_from_slice(value);
+    data.resize(data.len() + std::cmp::max(1, Translation::MIN_CAPACITY.saturating_sub(value.len())), 0);
+    for field in [key_offset, key.len(), context_offset, context.len(), value_offset, value.len()] {
+      index.extend_from_slice(&u32::try_from(field)?.to_le_bytes());
+    }
+  }
+
+  let lines = notes.lines.join("\n");
+  let mut out = Vec::with_capacity(base + data.len() + lines.len() + notes.report.len());
+  out.extend_from_slice(MAGIC);
+  out.extend_from_slice(&VERSION.to_le_bytes());
+  out.extend_from_slice(&checksum.to_le_bytes());
+  out.extend_from_slice(&u32::try_from(records.len() - templates.len())?.to_le_bytes());
+  out.extend_from_slice(&u32::try_from(templates.len())?.to_le_bytes());
+  out.extend_from_slice(&u32::try_from(lines.len())?.to_le_bytes());
+  out.extend_from_slice(&u32::try_from(notes.report.len())?.to_le_bytes());
+  out.extend_from_slice(&index);
+  out.extend_from_slice(&data);
+  out.extend_from_slice(lines.as_bytes());
+  out.extend_from_slice(notes.report);
+
+  // written aside and renamed, a running game keeps its mapping of the old file
+  let tmp = format!("{path}.tmp");
+  std::fs::write(&tmp, out)?;
+  std::fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+impl Compiled {
+  // fails when the file is missing, broken or was built from different sources
+  pub fn open(path: &str, checksum: u64) -> Result<Self> {
+    let file = std::fs::File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    if mmap.len() < HEADER || &mmap[..8] != MAGIC {
+      return Err(anyhow!("not a compiled dictionary"));
+    }
+    if u32_at(&mmap, 8) as u32 != VERSION {
+      return Err(anyhow!("unsupported version {}", u32_at(&mmap, 8)));
+    }
+    if u64_at(&mmap, 12) != checksum {
+      return Err(anyhow!("stale, sources have changed"));
+    }
+    let entries = u32_at(&mmap, 20);
+    let templates = u32_at(&mmap, 24);
+    let notes = u32_at(&mmap, 28);
+    let report = u32_at(&mmap, 32);
+    let compiled = Self {
+      mmap,
+      entries,
+      templates,
+      notes,
+      report,
+    };
+    compiled.validate()?;
+    Ok(compiled)
+  }
+
+  // bounds and terminators are checked once here, lookups rely on them afterwards
+  fn validate(&self) -> Result<()> {
+    let records = self.entries + self.templates;
+    if HEADER + records * RECORD + self.notes + self.report > self.mmap.len() {
+      return Err(anyhow!("truncated index"));
+    }
+    for i in 0..records {
+      let r = HEADER + i * RECORD;
+      let (key_offset, key_len) = (u32_at(&self.mmap, r), u32_at(&self.mmap, r + 4));
+      let (context_offset, context_len) = (u32_at(&self.mmap, r + 8), u32_at(&self.mmap, r + 12));
+      let (value_offset, value_len) = (u32_at(&self.mmap, r + 16), u32_at(&self.mmap, r + 20));
+      let padded = std::cmp::max(value_len + 1, Translation::MIN_CAPACITY);
+      if key_offset + key_len > self.mmap.len()
+        || context_offset + context_len > self.mmap.len()
+        || value_offset + padded > self.mmap.len()
+        || self.mmap[value_offset + value_len] != 0
+      {
+        return Err(anyhow!("record {i} is out of bounds"));
+      }
+      if i > 0 && i < self.entries && self.sort_key(i - 1) >= self.sort_key(i) {
+        return Err(anyhow!("index is not sorted"));
+      }
+    }
+    Ok(())
+  }
+
+  fn field(&self, i: usize, field: usize) -> &[u8] {
+    let r = HEADER + i * RECORD + field * 8;
+    let offset = u32_at(&self.mmap, r);
+    &self.mmap[offset..offset + u32_at(&self.mmap, r + 4)]
+  }
+
+  fn key(&self, i: usize) -> &[u8] {
+    self.field(i, 0)
+  }
+
+  fn context(&self, i: usize) -> &[u8] {
+    self.field(i, 1)
+  }
+
+  fn value(&self, i: usize) -> &[u8] {
+    self.field(i, 2)
+  }
+
+  fn sort_key(&self, i: usize) -> (&[u8], &[u8]) {
+    (self.key(i), self.context(i))
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries
+  }
+
+  // exact match on key and context, the returned value is followed by a NUL and padding inside the mapping
+  pub fn get(&self, key: &[u8], context: &str) -> Option<&[u8]> {
+    let target = (key, context.as_bytes());
+    let (mut lo, mut hi) = (0, self.entries);
+    while lo < hi {
+      let mid = (lo + hi) / 2;
+      match self.sort_key(mid).cmp(&target) {
+        std::cmp::Ordering::Less => lo = mid + 1,
+        std::cmp::Ordering::Greater => hi = mid,
+        std::cmp::Ordering::Equal => return Some(self.value(mid)),
+      }
+    }
+    None
+  }
+
+  // contexts were written from &str, so they are valid utf-8
+  pub fn entries(&self) -> impl Iterator<Item = (&[u8], &str, &[u8])> {
+    (0..self.entries).map(|i| (self.key(i), std::str::from_utf8(self.context(i)).unwrap_or(""), self.value(i)))
+  }
+
+  pub fn templates(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+    (self.entries..self.entries + self.templates).map(|i| (self.key(i), self.value(i)))
+  }
+
+  // the trailer, checked to fit by validate
+  pub fn notes(&self) -> impl Iterator<Item = &str> {
+    let end = self.mmap.len() - self.report;
+    std::str::from_utf8(&self.mmap[end - self.notes..end]).unwrap_or("").lines()
+  }
+
+  pub fn report(&self) -> &[u8] {
+    &self.mmap[self.mmap.len() - self.report..]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn built_file_round_trips() {
+    let path = std::env::temp_dir().join("dfint-compiled-round-trip.bin");
+    let path = path.to_str().unwrap();
+    let entries: Vec<(&[u8], &str, &[u8])> = vec![
+      (b"Stone", "", b"\xca\xe0\xec\xe5\xed\xfc"),
+      (b"Stone", "addst", b"ui"),
+      (b"Bar", "", b""),
+      (b"Order", "", b"x"),
+    ];
+    let templates = vec![(b"{number:1} days".to_vec(), b"{1} d".to_vec())];
+    let notes = Notes {
+      lines: &[String::from("user.csv:1: \"Stone\" overrides base.csv:1"), String::from("layer user.csv")],
+      report: b"dictionary base.csv, user.csv\n",
+    };
+    build(path, 42, entries.into_iter(), &templates, notes).unwrap();
+
+    assert!(Compiled::open(path, 41).is_err());
+    let compiled = Compiled::open(path, 42).unwrap();
+    assert_eq!(compiled.len(), 4);
+    assert_eq!(compiled.get(b"Stone", ""), Some(b"\xca\xe0\xec\xe5\xed\xfc".as_slice()));
+    assert_eq!(compiled.get(b"Stone", "addst"), Some(b"ui".as_slice()));
+    assert_eq!(compiled.get(b"Stone", "addst_top"), None);
+    assert_eq!(compiled.get(b"Order", ""), Some(b"x".as_slice()));
+    assert_eq!(compiled.get(b"Ore", ""), None);
+    let value = compiled.get(b"Order", "").unwrap();
+    let padded = unsafe { std::slice::from_raw_parts(value.as_ptr(), Translation::MIN_CAPACITY) };
+    assert_eq!(&padded[1..], &[0u8; 15]);
+    assert_eq!(
+      compiled.templates().collect::<Vec<_>>(),
+      vec![(b"{number:1} days".as_slice(), b"{1} d".as_slice())]
+    );
+    assert_eq!(
+      compiled.notes().collect::<Vec<_>>(),
+      vec!["user.csv:1: \"Stone\" overrides base.csv:1", "layer user.csv"]
+    );
+    assert_eq!(compiled.report(), b"dictionary base.csv, user.csv\n");
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn broken_files_are_rejected() {
+    let path = std::env::temp_dir().join("dfint-compiled-broken.bin");
+    let path = path.to_str().unwrap();
+    let notes = Notes {
+      lines: &[],
+      report: b"",
+    };
+    build(path, 7, vec![(b"a".as_slice(), "", b"b".as_slice())].into_iter(), &[], notes).unwrap();
+    let mut data = std::fs::read(path).unwrap();
+    data.truncate(data.len() - 10);
+    std::fs::write(path, &data).unwrap();
+    assert!(Compiled::open(path, 7).is_err());
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn stale_builds_are_removed() {
+    let dir = std::env::temp_dir().join("dfint-compiled-versions");
+    std::fs::create_dir_all(&dir).unwrap();
+    let base = String::from(dir.join("dictionary.bin").to_str().unwrap());
+    let (old, new) = (versioned(&base, 1), versioned(&base, 2));
+    assert_eq!(Path::new(&new).file_name().unwrap(), "dictionary.0000000000000002.bin");
+    for path in [&base, &old, &new] {
+      std::fs::write(path, b"").unwrap();
+    }
+    std::fs::write(dir.join("dictionary.csv"), b"").unwrap();
+
+    remove_stale(&base, &new);
+    let mut left: Vec<String> =
+      std::fs::read_dir(&dir).unwrap().map(|f| f.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    left.sort();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(left, vec!["dictionary.0000000000000002.bin", "dictionary.csv"]);
+  }
+
+  #[test]
+  fn checksum_follows_sources() {
+    let dir = std::env::temp_dir().join("dfint-compiled-checksum");
+    std::fs::create_dir_all(&dir).unwrap();
+    let base = String::from(dir.join("base.csv").to_str().unwrap());
+    let user = String::from(dir.join("user.csv").to_str().unwrap());
+    std::fs::write(&base, b"\"a\",\"b\"\n").unwrap();
+    let layers = vec![base.clone(), user.clone()];
+
+    let first = checksum(&layers).unwrap();
+    std::fs::write(&user, b"\"a\",\"c\"\n").unwrap();
+    let second = checksum(&layers).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_ne!(first, second);
+    assert!(checksum(&layers).is_err());
+  }
+}
@@ -1,45 +1,179 @@
 #[allow(unused_imports)]
 use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
 use std::path::Path;
+use toml::{Table, Value};
 
 use crate::{
-  constants::{PATH_CONFIG, PATH_EXE, PATH_OFFSETS},
   encoding::Encoding,
-  utils,
+  identity::GameIdentity,
+  paths::{PathsSection, PATHS},
+  registry, signatures,
 };
 
-#[static_init::dynamic]
+#[static_init::dynamic(lazy)]
 pub static CONFIG: Config = Config::new();
 
 #[allow(dead_code)]
 pub struct Config {
   pub metadata: ConfigMetadata,
   pub settings: Settings,
-  pub offset_metadata: OffsetsMetadata,
+  pub hooks: HooksSettings,
+  pub identity: GameIdentity,
+  // None for a build no offsets fit, nothing gets hooked then
+  pub offset_metadata: Option<OffsetsMetadata>,
   pub offset: Option<OffsetsValues>,
   pub symbol: Option<SymbolsValues>,
+  // outcome of the signature scan, when offsets had to be discovered
+  pub discovered: Option<Vec<(&'static str, Result<usize>)>>,
+  // offsets candidates that did not fit the running executable, with the reason
+  pub rejected: Vec<(String, String)>,
   pub hook_version: String,
   pub encoding: Encoding,
+  // everything wrong with the installation, shown to the user once the log is up
+  pub problems: Vec<String>,
 }
 
-#[derive(Deserialize)]
+// sections config.toml may have, the others are reported
+const SECTIONS: &[&str] = &["metadata", "settings", "paths", "hooks"];
+
 pub struct MainConfig {
   pub metadata: ConfigMetadata,
   pub settings: Settings,
+  pub hooks: HooksSettings,
 }
 
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConfigMetadata {
   pub name: String,
 }
 
+impl Default for ConfigMetadata {
+  fn default() -> Self {
+    Self {
+      name: String::from("dfint localization hook"),
+    }
+  }
+}
+
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Settings {
   pub log_level: usize,
   pub log_file: String,
   pub enable_search: bool,
   pub enable_translation: bool,
   pub watchdog: bool,
+  pub dictionary_max_growth: f64,
+  pub capture_untranslated: bool,
+  pub capture_interval: u64,
+  pub hook_panic_limit: u32,
+  pub enable_bidi: bool,
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Self {
+      log_level: 2,
+      log_file: String::from("./dfint-data/dfint-log.log"),
+      enable_search: true,
+      enable_translation: true,
+      watchdog: true,
+      dictionary_max_growth: 3.0,
+      capture_untranslated: false,
+      capture_interval: 60,
+      hook_panic_limit: 3,
+      enable_bidi: false,
+    }
+  }
+}
+
+impl Settings {
+  // out of range values are replaced by their defaults
+  fn validate(&mut self, problems: &mut Vec<String>) {
+    let default = Settings::default();
+    if self.log_level > 5 {
+      problems.push(format!(
+        "[settings] log_level {} is out of range 0-5, using {}",
+        self.log_level, default.log_level
+      ));
+      self.log_level = default.log_level;
+    }
+    if self.log_file.trim().is_empty() {
+      problems.push(format!("[settings] log_file is empty, using {}", default.log_file));
+      self.log_file = default.log_file;
+    }
+    if self.dictionary_max_growth.is_nan() || self.dictionary_max_growth <= 0.0 {
+      problems.push(format!(
+        "[settings] dictionary_max_growth {} has to be positive, using {}",
+        self.dictionary_max_growth, default.dictionary_max_growth
+      ));
+      self.dictionary_max_growth = default.dictionary_max_growth;
+    }
+    if self.capture_interval == 0 {
+      problems.push(format!(
+        "[settings] capture_interval has to be at least 1, using {}",
+        default.capture_interval
+      ));
+      self.capture_interval = default.capture_interval;
+    }
+  }
+}
+
+// [hooks], every hook is on unless switched off here by the name it was registered with
+#[derive(Deserialize, Default)]
+#[serde(transparent)]
+pub struct HooksSettings(HashMap<String, bool>);
+
+impl HooksSettings {
+  pub fn enabled(&self, hook: &str) -> bool {
+    self.0.get(hook).copied().unwrap_or(true)
+  }
+
+  fn validate(&mut self, problems: &mut Vec<String>) {
+    let names: Vec<&str> = registry::sorted().iter().map(|hook| hook.name).collect();
+    self.0.retain(|name, _| {
+      let known = names.contains(&name.as_str());
+      if !known {
+        problems.push(format!("[hooks] unknown hook \"{name}\", expected one of {}", names.join(", ")));
+      }
+      known
+    });
+  }
+}
+
+// reads one section key by key, so every bad key is reported and the rest still applies;
+// missing keys and sections take their defaults
+pub fn section<T: for<'de> serde::Deserialize<'de> + Default>(
+  table: &Table,
+  name: &str,
+  problems: &mut Vec<String>,
+) -> T {
+  let Some(value) = table.get(name) else {
+    return T::default();
+  };
+  let Some(section) = value.as_table() else {
+    problems.push(format!("[{name}] is not a table, using defaults"));
+    return T::default();
+  };
+  let mut valid = Table::new();
+  for (key, value) in section {
+    let single = Table::from_iter([(key.clone(), value.clone())]);
+    match Value::Table(single).try_into::<T>() {
+      Ok(_) => {
+        valid.insert(key.clone(), value.clone());
+      }
+      Err(err) => problems.push(format!("[{name}] {key}: {}", err.message())),
+    }
+  }
+  Value::Table(valid).try_into::<T>().unwrap_or_default()
+}
+
+// offsets.toml either describes one build, or several as [[versions]] tables of the same shape
+#[derive(Deserialize)]
+struct OffsetsBundle {
+  versions: Vec<Offsets>,
 }
 
 #[derive(Deserialize)]
@@ -47,13 +181,23 @@ pub struct Offsets {
   pub metadata: OffsetsMetadata,
   pub offsets: Option<OffsetsValues>,
   pub symbols: Option<SymbolsValues>,
+  pub signatures: Option<SignaturesValues>,
 }
 
 #[derive(Deserialize)]
 pub struct OffsetsMetadata {
   pub name: String,
   pub version: String,
-  pub checksum: u32,
+  // any of these identify the build, see identity.rs
+  #[serde(default)]
+  pub checksum: Option<u32>,
+  #[serde(default)]
+  pub build_id: Option<String>,
+  #[serde(default)]
+  pub size: Option<u64>,
+  // libstdc++, libc++ or msvc, probed from the game when missing
+  #[serde(default)]
+  pub string_layout: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -72,6 +216,27 @@ pub struct OffsetsValues {
   pub capitalize_string_words: Option<usize>,
   pub capitalize_string_first_word: Option<usize>,
   pub utf_input: Option<usize>,
+  // the game's own, for std::string buffers the hook grows
+  pub operator_new: Option<usize>,
+  pub operator_delete: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct SignaturesValues {
+  pub string_copy_n: Option<String>,
+  pub string_append_n: Option<String>,
+  pub std_string_ctor: Option<String>,
+  pub addst: Option<String>,
+  pub addst_top: Option<String>,
+  pub addst_flag: Option<String>,
+  pub standardstringentry: Option<String>,
+  pub simplify_string: Option<String>,
+  pub upper_case_string: Option<String>,
+  pub lower_case_string: Option<String>,
+  pub capitalize_string_words: Option<String>,
+  pub capitalize_string_first_word: Option<String>,
+  pub operator_new: Option<String>,
+  pub operator_delete: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -93,64 +258,313 @@ pub struct SymbolsValues {
 
 impl Config {
   pub fn new() -> Self {
-    let checksum = Self::checksum(PATH_EXE).unwrap_or(0);
-    let main_config = Self::parse_toml::<MainConfig>(PATH_CONFIG).unwrap();
+    let mut problems = Vec::new();
+    let identity = GameIdentity::new(&PATHS.exe);
+    let mut main_config = Self::load(&PATHS.config, &mut problems);
+    main_config.settings.log_file = PATHS.locate(&main_config.settings.log_file);
     let encoding = Encoding::new();
+    if !encoding.parsed && main_config.settings.enable_search {
+      problems.push(format!("unable to read encoding {}, search is disabled", PATHS.encoding));
+    }
+    if !Path::new(&PATHS.dictionary).exists() && main_config.settings.enable_translation {
+      problems.push(format!("dictionary {} not found, nothing will be translated", PATHS.dictionary));
+    }
     let hook_version = match option_env!("HOOK_VERSION") {
       Some(version) => String::from(version),
       None => String::from("not-defined"),
     };
 
-    let (offset_metadata, offset, symbol) = match Self::parse_toml::<Offsets>(PATH_OFFSETS) {
-      Ok(o) if o.metadata.checksum == checksum => (o.metadata, o.offsets, o.symbols),
+    let candidates = Self::candidates();
+    if candidates.is_empty() {
+      problems.push(format!("no offsets found at {} or in {}", PATHS.offsets, PATHS.offsets_dir));
+    }
+    let (matched, unmatched, rejected) = Self::select(candidates, &identity);
+    // an unknown build, look the hooked functions up by their code instead,
+    // with the signatures of the first candidate that finds any of them
+    let discovered = match matched {
+      Some(_) => None,
+      // Linux hooks are looked up by name in the symbol tables, discovered offsets would never be used
+      None if cfg!(target_os = "linux") => None,
+      None => unmatched.into_iter().find_map(|o| {
+        let (offsets, report) = signatures::discover(o.signatures.as_ref()?);
+        report.iter().any(|(_, result)| result.is_ok()).then_some((o, offsets, report))
+      }),
+    };
+    let (offset_metadata, offset, symbol, discovered) = match (matched, discovered) {
+      (Some(o), _) => (Some(o.metadata), o.offsets, o.symbols, None),
+      (None, Some((o, offsets, report))) => (
+        Some(OffsetsMetadata {
+          name: o.metadata.name,
+          version: format!("{} (signature scan)", o.metadata.version),
+          checksum: None,
+          build_id: None,
+          size: None,
+          string_layout: o.metadata.string_layout,
+        }),
+        Some(offsets),
+        o.symbols,
+        Some(report),
+      ),
       _ => {
-        utils::message_box(
-          "dfint hook error",
-          format!("This DF version is not supported.\nDF checksum: 0x{:x}", checksum).as_str(),
-          utils::MessageIconType::Error,
-        );
-        (
-          OffsetsMetadata {
-            name: String::from("not found"),
-            version: String::from("not found"),
-            checksum,
-          },
-          None,
-          None,
-        )
+        problems.push(format!(
+          "this DF version is not supported, DF: {identity}, offsets checked: {}",
+          rejected.len()
+        ));
+        (None, None, None, None)
       }
     };
 
     Self {
       metadata: main_config.metadata,
       settings: main_config.settings,
+      hooks: main_config.hooks,
+      identity,
       offset_metadata,
       offset,
       symbol,
+      discovered,
+      rejected,
       hook_version,
       encoding,
+      problems,
+    }
+  }
+
+  fn load(path: &str, problems: &mut Vec<String>) -> MainConfig {
+    match std::fs::read_to_string(path) {
+      Ok(content) => Self::parse_main(path, &content, problems),
+      // every setting has a default, attach logs that the file is missing
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::parse_main(path, "", problems),
+      Err(err) => {
+        problems.push(format!("unable to read {path}, using defaults: {err}"));
+        Self::parse_main(path, "", problems)
+      }
     }
   }
 
-  #[cfg(target_os = "windows")]
-  fn checksum(path: &str) -> Result<u32> {
-    use exe::{VecPE, PE};
-    let pefile = VecPE::from_disk_file(Path::new(path))?;
-    Ok(pefile.get_nt_headers_64()?.file_header.time_date_stamp)
+  fn parse_main(path: &str, content: &str, problems: &mut Vec<String>) -> MainConfig {
+    let table = content.parse::<Table>().unwrap_or_else(|err| {
+      problems.push(format!("{path} is not valid toml, using defaults: {}", err.message()));
+      Table::new()
+    });
+    for key in table.keys().filter(|key| !SECTIONS.contains(&key.as_str())) {
+      problems.push(format!("unknown section [{key}]"));
+    }
+    // [paths] is applied by PATHS, only checked here
+    section::<PathsSection>(&table, "paths", problems);
+    let mut settings: Settings = section(&table, "settings", problems);
+    settings.validate(problems);
+    let mut hooks: HooksSettings = section(&table, "hooks", problems);
+    hooks.validate(problems);
+    MainConfig {
+      metadata: section(&table, "metadata", problems),
+      settings,
+      hooks,
+    }
   }
 
-  #[cfg(target_os = "linux")]
-  fn checksum(path: &str) -> Result<u32> {
-    let mut crc = checksum::crc::Crc::new(path);
-    match crc.checksum() {
-      Ok(checksum) => Ok(checksum.crc32),
-      Err(e) => Err(anyhow!("Checksum error {:?}", e).into()),
+  // offsets.toml first, then every offsets/*.toml in lexical order
+  fn candidates() -> Vec<(String, Result<Vec<Offsets>>)> {
+    let mut files = vec![PATHS.offsets.clone()];
+    if let Ok(entries) = std::fs::read_dir(&PATHS.offsets_dir) {
+      let mut found: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| path.to_str().map(String::from))
+        .collect();
+      found.sort();
+      files.extend(found);
     }
+    files
+      .into_iter()
+      .filter(|path| Path::new(path).exists())
+      .map(|path| {
+        let parsed = std::fs::read_to_string(&path).map_err(Into::into).and_then(|c| Self::parse_offsets(&c));
+        (path, parsed)
+      })
+      .collect()
+  }
+
+  fn parse_offsets(content: &str) -> Result<Vec<Offsets>> {
+    if content.parse::<toml::Table>()?.contains_key("versions") {
+      return Ok(toml::from_str::<OffsetsBundle>(content)?.versions);
+    }
+    Ok(vec![toml::from_str::<Offsets>(content)?])
+  }
+
+  // the first candidate matching the executable wins, the others are returned for signature scanning
+  // and listed with the reason they were rejected
+  fn select(
+    candidates: Vec<(String, Result<Vec<Offsets>>)>,
+    identity: &GameIdentity,
+  ) -> (Option<Offsets>, Vec<Offsets>, Vec<(String, String)>) {
+    let mut matched = None;
+    let mut unmatched = Vec::new();
+    let mut rejected = Vec::new();
+    for (source, parsed) in candidates {
+      let versions = match parsed {
+        Ok(versions) => versions,
+        Err(err) => {
+          rejected.push((source, format!("unable to parse, {err}")));
+          continue;
+        }
+      };
+      for o in versions {
+        let reason = match matched {
+          Some(_) => String::from("an earlier entry matched"),
+          None => match identity.matches(&o.metadata) {
+            Ok(_) => {
+              matched = Some(o);
+              continue;
+            }
+            Err(reason) => reason,
+          },
+        };
+        rejected.push((format!("{source} \"{}\" {}", o.metadata.name, o.metadata.version), reason));
+        unmatched.push(o);
+      }
+    }
+    (matched, unmatched, rejected)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SINGLE: &str = r#"
+[metadata]
+name = "win64 steam"
+version = "50.13"
+checksum = 0x1234
+
+[offsets]
+addst = 0x100
+"#;
+
+  const BUNDLE: &str = r#"
+[[versions]]
+[versions.metadata]
+name = "linux classic"
+version = "50.13"
+size = 2048
+
+[[versions]]
+[versions.metadata]
+name = "linux itch"
+version = "50.13"
+build_id = "9abc"
+size = 1024
+[versions.offsets]
+addst = 0x200
+"#;
+
+  fn candidates() -> Vec<(String, Result<Vec<Offsets>>)> {
+    vec![
+      (String::from("offsets.toml"), Config::parse_offsets(SINGLE)),
+      (String::from("offsets/50.13.toml"), Config::parse_offsets(BUNDLE)),
+      (
+        String::from("offsets/broken.toml"),
+        Config::parse_offsets("[metadata]\nname = 1\n"),
+      ),
+    ]
+  }
+
+  #[test]
+  fn matching_version_is_picked_from_a_bundle() {
+    let (matched, unmatched, rejected) = Config::select(candidates(), &GameIdentity::known("9abc", 1024, None));
+    let matched = matched.unwrap();
+    assert_eq!(matched.metadata.name, "linux itch");
+    assert_eq!(matched.offsets.unwrap().addst, Some(0x200));
+    assert_eq!(unmatched.len(), 2);
+    assert_eq!(
+      rejected.iter().map(|(source, _)| source.as_str()).collect::<Vec<_>>(),
+      vec![
+        "offsets.toml \"win64 steam\" 50.13",
+        "offsets/50.13.toml \"linux classic\" 50.13",
+        "offsets/broken.toml"
+      ]
+    );
+    assert_eq!(rejected[1].1, "size 2048 does not match");
+  }
+
+  #[test]
+  fn nothing_matches_an_unknown_build() {
+    let (matched, unmatched, rejected) = Config::select(candidates(), &GameIdentity::known("dead", 4096, None));
+    assert!(matched.is_none());
+    assert_eq!(unmatched.len(), 3);
+    assert_eq!(rejected.len(), 4);
+  }
+
+  #[test]
+  fn missing_config_is_not_a_problem() {
+    let mut problems = Vec::new();
+    let config = Config::load("/nonexistent/dfint-data/config.toml", &mut problems);
+    assert!(problems.is_empty());
+    assert!(config.settings.enable_translation);
+  }
+
+  #[test]
+  fn every_bad_setting_is_reported() {
+    let content = r#"
+[settings]
+log_level = "debug"
+enable_search = false
+watchdg = true
+capture_interval = 0
+
+[setings]
+"#;
+    let mut problems = Vec::new();
+    let MainConfig { settings, .. } = Config::parse_main("config.toml", content, &mut problems);
+    assert_eq!(problems.len(), 4, "{problems:?}");
+    assert_eq!(problems[0], "unknown section [setings]");
+    assert!(problems.iter().any(|p| p.starts_with("[settings] log_level: invalid type")));
+    assert!(problems.iter().any(|p| p.starts_with("[settings] watchdg: unknown field")));
+    assert!(problems.iter().any(|p| p.starts_with("[settings] capture_interval has to be")));
+    assert!(!settings.enable_search);
+    assert_eq!(settings.log_level, 2);
+    assert_eq!(settings.capture_interval, 60);
+    assert!(settings.watchdog);
+  }
+
+  #[test]
+  fn out_of_range_settings_fall_back_to_defaults() {
+    let mut problems = Vec::new();
+    let mut settings = Settings {
+      log_level: 9,
+      log_file: String::from(" "),
+      dictionary_max_growth: f64::NAN,
+      ..Default::default()
+    };
+    settings.validate(&mut problems);
+    assert_eq!(problems.len(), 3);
+    assert_eq!(settings.log_level, 2);
+    assert_eq!(settings.log_file, Settings::default().log_file);
+    assert_eq!(settings.dictionary_max_growth, 3.0);
+
+    let mut settings = Settings::default();
+    settings.validate(&mut problems);
+    assert_eq!(problems.len(), 3);
   }
 
-  fn parse_toml<T: for<'de> serde::Deserialize<'de>>(path: &str) -> Result<T> {
-    let content = std::fs::read_to_string(Path::new(path))?;
-    let data: T = toml::from_str(content.as_str())?;
-    Ok(data)
+  #[test]
+  fn hooks_can_be_switched_off_one_by_one() {
+    let content = r#"
+[hooks]
+std_string_ctor = false
+capitalize_string_word = false
+addst_top = "no"
+"#;
+    let mut problems = Vec::new();
+    let MainConfig { hooks, .. } = Config::parse_main("config.toml", content, &mut problems);
+    assert!(!hooks.enabled("std_string_ctor"));
+    assert!(hooks.enabled("capitalize_string_words"));
+    assert!(hooks.enabled("addst"));
+    assert!(hooks.enabled("addst_top"));
+    assert_eq!(problems.len(), 2);
+    assert!(problems.iter().any(|p| p.starts_with("[hooks] addst_top: invalid type")));
+    assert!(problems.iter().any(|p| p.starts_with("[hooks] unknown hook \"capitalize_string_word\"")));
   }
 }
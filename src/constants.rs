@@ -1,13 +1,27 @@
 #[cfg(target_os = "windows")]
-pub const PATH_SDL2: &'static str = "SDL2.dll";
+pub const PATH_SDL2: &str = "SDL2.dll";
 #[cfg(target_os = "linux")]
-pub const PATH_SDL2: &'static str = "libSDL2-2.0.so.0";
+pub const PATH_SDL2: &str = "libSDL2-2.0.so.0";
 #[cfg(target_os = "windows")]
-pub const PATH_EXE: &'static str = "./Dwarf Fortress.exe";
+pub const PATH_EXE: &str = "./Dwarf Fortress.exe";
 #[cfg(target_os = "linux")]
-pub const PATH_EXE: &'static str = "./dwarfort";
+pub const PATH_EXE: &str = "./dwarfort";
 
-pub const PATH_ENCODING: &'static str = "./dfint-data/encoding.toml";
-pub const PATH_CONFIG: &'static str = "./dfint-data/config.toml";
-pub const PATH_OFFSETS: &'static str = "./dfint-data/offsets.toml";
-pub const PATH_DICTIONARY: &'static str = "./dfint-data/dictionary.csv";
+// file names inside the data directory, see paths.rs for how the directory itself is found
+pub const DIR_DATA: &str = "dfint-data";
+pub const FILE_ENCODING: &str = "encoding.toml";
+pub const FILE_CONFIG: &str = "config.toml";
+pub const FILE_OFFSETS: &str = "offsets.toml";
+pub const DIR_OFFSETS: &str = "offsets";
+pub const FILE_DICTIONARY: &str = "dictionary.csv";
+pub const DIR_DICTIONARY_LAYERS: &str = "dictionary.d";
+pub const FILE_DICTIONARY_USER: &str = "dictionary.user.csv";
+pub const FILE_DICTIONARY_COMPILED: &str = "dictionary.bin";
+pub const FILE_UNTRANSLATED: &str = "untranslated.csv";
+pub const FILE_UNTRANSLATED_STATS: &str = "untranslated.stats.csv";
+
+pub const ENV_DATA: &str = "DFINT_DATA";
+pub const ENV_CONFIG: &str = "DFINT_CONFIG";
+pub const ENV_OFFSETS: &str = "DFINT_OFFSETS";
+pub const ENV_ENCODING: &str = "DFINT_ENCODING";
+pub const ENV_DICTIONARY: &str = "DFINT_DICTIONARY";
@@ -0,0 +1,353 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::CONFIG;
+use crate::paths::PATHS;
+use crate::registry;
+
+const RECENT_LIMIT: usize = 32;
+const RECENT_KEY_LIMIT: usize = 160;
+
+// (hook, key) of the last translated strings, oldest first. Entries are recycled once it is full,
+// so remembering a string does not allocate in the middle of a frame.
+#[static_init::dynamic(lazy)]
+static RECENT: Mutex<VecDeque<(String, Vec<u8>)>> = Mutex::new(VecDeque::with_capacity(RECENT_LIMIT));
+
+pub fn remember(context: &str, key: &[u8]) {
+  // a crash report without the latest string beats a game thread waiting on a lock
+  let Ok(mut recent) = RECENT.try_lock() else {
+    return;
+  };
+  let (mut hook, mut value) = match recent.len() >= RECENT_LIMIT {
+    true => recent.pop_front().unwrap_or_default(),
+    false => Default::default(),
+  };
+  hook.clear();
+  hook.push_str(context);
+  value.clear();
+  value.extend_from_slice(&key[..key.len().min(RECENT_KEY_LIMIT)]);
+  recent.push_back((hook, value));
+}
+
+// what is known about the session before anything goes wrong, the signal handler gets it prebuilt
+fn header() -> String {
+  let offsets = match &CONFIG.offset_metadata {
+    Some(metadata) => format!(
+      "{} {}, checksum {}",
+      metadata.name,
+      metadata.version,
+      metadata.checksum.map_or_else(|| String::from("none"), |c| format!("0x{c:x}"))
+    ),
+    None => String::from("none"),
+  };
+  [
+    format!("hook version: {}", CONFIG.hook_version),
+    format!("offsets: {offsets}"),
+    format!("game: {}", CONFIG.identity),
+  ]
+  .join("\n")
+}
+
+fn render(reason: &str, details: &str, recent: &[(String, Vec<u8>)]) -> String {
+  let mut lines = vec![
+    format!(
+      "dfint crash report, {}",
+      chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+    ),
+    format!("reason: {reason}"),
+    header(),
+    String::new(),
+    String::from("hooks:"),
+  ];
+  for (name, _, method, state, panics) in registry::status() {
+    lines.push(format!("  {name}: {state}, by {method:?}, {panics} panics"));
+  }
+  lines.push(String::new());
+  lines.push(String::from("last translated strings, oldest first:"));
+  for (hook, key) in recent {
+    lines.push(format!("  [{hook}] {}", String::from_utf8_lossy(key)));
+  }
+  lines.push(String::new());
+  lines.push(String::from("backtrace:"));
+  lines.push(String::from(details));
+  lines.join("\n")
+}
+
+fn report_path() -> PathBuf {
+  let name = format!("crash-{}.txt", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+  Path::new(&PATHS.data).join(name)
+}
+
+fn write(reason: &str, details: &str) -> Option<PathBuf> {
+  let recent: Vec<(String, Vec<u8>)> = RECENT.try_lock().map(|r| r.iter().cloned().collect()).unwrap_or_default();
+  let path = report_path();
+  std::fs::write(&path, render(reason, details, &recent)).ok()?;
+  Some(path)
+}
+
+// panics caught inside detours are only logged, everything else gets a crash report
+pub fn install() {
+  let previous = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |info| {
+    let details = format!("{info}\n{:?}", backtrace::Backtrace::new());
+    if registry::in_detour() {
+      registry::note_panic(details);
+    } else {
+      match write("panic", &details) {
+        Some(path) => log::error!("{info}, crash report written to {}", path.display()),
+        None => log::error!("{info}, unable to write crash report"),
+      }
+    }
+    previous(info);
+  }));
+  #[cfg(target_os = "linux")]
+  signals::install();
+}
+
+#[cfg(target_os = "linux")]
+mod signals {
+  use std::ffi::CString;
+  use std::os::unix::ffi::OsStrExt;
+  use std::sync::OnceLock;
+
+  use super::RECENT;
+  use crate::registry::{self, Method};
+
+  const SIGNALS: [libc::c_int; 2] = [libc::SIGSEGV, libc::SIGABRT];
+  const FRAMES: usize = 64;
+  // a stack overflow leaves no room on the thread's own stack, the handler runs on this one instead
+  const ALT_STACK_SIZE: usize = 256 * 1024;
+
+  // report path and header, built while allocating is still fine; the time in the name is the session start
+  static PREPARED: OnceLock<(CString, Vec<u8>)> = OnceLock::new();
+  // what was installed for each of SIGNALS before us, e.g. a crash reporter of SDL or the Steam runtime
+  static PREVIOUS: [OnceLock<libc::sigaction>; SIGNALS.len()] = [const { OnceLock::new() }; SIGNALS.len()];
+
+  // Output for a signal handler: a fault inside malloc leaves the allocator locked, so nothing here allocates,
+  // formats or takes a lock it could wait on. open, read and write are async-signal-safe. The backtrace is the
+  // exception, unwinding may take the loader lock, so it comes last.
+  struct Fd(libc::c_int);
+
+  impl Fd {
+    fn bytes(&self, bytes: &[u8]) {
+      let mut rest = bytes;
+      while !rest.is_empty() {
+        let written = unsafe { libc::write(self.0, rest.as_ptr() as *const libc::c_void, rest.len()) };
+        if written <= 0 {
+          return;
+        }
+        rest = &rest[written as usize..];
+      }
+    }
+
+    fn number(&self, mut value: u64, radix: u64) {
+      let mut buf = [0u8; 20];
+      let mut i = buf.len();
+      loop {
+        i -= 1;
+        buf[i] = b"0123456789abcdef"[(value % radix) as usize];
+        value /= radix;
+        if value == 0 {
+          break;
+        }
+      }
+      self.bytes(&buf[i..]);
+    }
+
+    fn copy(&self, path: &[u8]) {
+      let from = unsafe { libc::open(path.as_ptr() as *const libc::c_char, libc::O_RDONLY) };
+      if from < 0 {
+        return;
+      }
+      let mut buf = [0u8; 4096];
+      loop {
+        let read = unsafe { libc::read(from, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if read <= 0 {
+          break;
+        }
+        self.bytes(&buf[..read as usize]);
+      }
+      unsafe { libc::close(from) };
+    }
+  }
+
+  fn method(method: Method) -> &'static [u8] {
+    match method {
+      Method::Offset => b"Offset",
+      Method::Symbol => b"Symbol",
+      Method::Bypass => b"Bypass",
+    }
+  }
+
+  // the same sections as super::render, with raw return addresses and the memory map to place them
+  fn report(out: &Fd, header: &[u8], reason: &[u8]) {
+    out.bytes(header);
+    out.bytes(b"\nreason: ");
+    out.bytes(reason);
+    out.bytes(b"\n\nhooks:\n");
+    for hook in registry::HOOKS.iter().filter(|hook| hook.method != Method::Bypass) {
+      out.bytes(b"  ");
+      out.bytes(hook.name.as_bytes());
+      out.bytes(b": ");
+      out.bytes(hook.state().name().as_bytes());
+      out.bytes(b", by ");
+      out.bytes(method(hook.method));
+      out.bytes(b", ");
+      out.number(hook.panics() as u64, 10);
+      out.bytes(b" panics\n");
+    }
+    out.bytes(b"\nlast translated strings, oldest first:\n");
+    // a thread that crashed while remembering a string holds the lock, the list is skipped then
+    if let Ok(recent) = RECENT.try_lock() {
+      for (hook, key) in recent.iter() {
+        out.bytes(b"  [");
+        out.bytes(hook.as_bytes());
+        out.bytes(b"] ");
+        out.bytes(key);
+        out.bytes(b"\n");
+      }
+    }
+    out.bytes(b"\nmemory map:\n");
+    out.copy(b"/proc/self/maps\0");
+    out.bytes(b"\nbacktrace:\n");
+    let mut frames = [0usize; FRAMES];
+    let mut count = 0;
+    unsafe {
+      backtrace::trace_unsynchronized(|frame| {
+        frames[count] = frame.ip() as usize;
+        count += 1;
+        count < FRAMES
+      })
+    };
+    for ip in &frames[..count] {
+      out.bytes(b"  0x");
+      out.number(*ip as u64, 16);
+      out.bytes(b"\n");
+    }
+  }
+
+  // After the report the previous action is put back and gets the signal too: returning re-runs the faulting
+  // instruction for a real fault, a signal that was sent, like the one abort() raises, is raised again. With the
+  // default action the game still crashes and dumps core.
+  extern "C" fn handler(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    if let Some((path, header)) = PREPARED.get() {
+      let reason: &[u8] = match signal {
+        libc::SIGSEGV => b"signal SIGSEGV",
+        _ => b"signal SIGABRT",
+      };
+      let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC, 0o644) };
+      if fd >= 0 {
+        report(&Fd(fd), header, reason);
+        unsafe { libc::close(fd) };
+      }
+    }
+    let previous = SIGNALS.iter().position(|s| *s == signal).and_then(|i| PREVIOUS[i].get());
+    unsafe {
+      match previous {
+        Some(previous) => libc::sigaction(signal, previous, std::ptr::null_mut()),
+        None => libc::sigaction(signal, &default(), std::ptr::null_mut()),
+      };
+      if (*info).si_code <= 0 {
+        libc::raise(signal);
+      }
+    }
+  }
+
+  fn default() -> libc::sigaction {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = libc::SIG_DFL;
+    action
+  }
+
+  // sigaltstack is per thread, this covers the thread that loads the hook, DF's main thread
+  unsafe fn alt_stack() -> bool {
+    let stack = Box::leak(vec![0u8; ALT_STACK_SIZE].into_boxed_slice());
+    let alt = libc::stack_t {
+      ss_sp: stack.as_mut_ptr() as *mut libc::c_void,
+      ss_flags: 0,
+      ss_size: stack.len(),
+    };
+    libc::sigaltstack(&alt, std::ptr::null_mut()) == 0
+  }
+
+  pub fn install() {
+    let Ok(path) = CString::new(super::report_path().as_os_str().as_bytes()) else {
+      return;
+    };
+    let header = format!(
+      "dfint crash report, session started {}\n{}",
+      chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+      super::header()
+    );
+    let _ = PREPARED.set((path, header.into_bytes()));
+    // RECENT is lazy, its allocating initializer must not run inside the handler
+    let _ = &*RECENT;
+    if !unsafe { alt_stack() } {
+      log::warn!("unable to set up a signal stack, stack overflows get no crash report");
+    }
+    for (signal, previous) in SIGNALS.into_iter().zip(&PREVIOUS) {
+      unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction =
+          handler as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut old: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(signal, &action, &mut old) != 0 {
+          log::warn!("unable to install crash handler for signal {signal}");
+          continue;
+        }
+        let _ = previous.set(old);
+      }
+    }
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_report_is_written_without_formatting() {
+      let path = std::env::temp_dir().join("dfint-crash-signal.txt");
+      let file = std::fs::File::create(&path).unwrap();
+      {
+        use std::os::fd::AsRawFd;
+        report(&Fd(file.as_raw_fd()), b"dfint crash report", b"signal SIGSEGV");
+      }
+      drop(file);
+      let report = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).into_owned();
+      std::fs::remove_file(&path).unwrap();
+
+      assert!(report.starts_with("dfint crash report\nreason: signal SIGSEGV\n\nhooks:\n"));
+      assert!(report.contains("  addst: detached, by Symbol, 0 panics\n"));
+      assert!(report.contains("\n\nlast translated strings, oldest first:\n"));
+      assert!(report.contains("\nmemory map:\n"));
+      assert!(report.contains("\nbacktrace:\n  0x"));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn report_lists_hooks_and_recent_strings() {
+    for i in 0..RECENT_LIMIT + 5 {
+      remember("addst", format!("string {i}").as_bytes());
+    }
+    remember("addst_top", &[b'x'; 400]);
+    let recent: Vec<(String, Vec<u8>)> = RECENT.lock().unwrap().iter().cloned().collect();
+    assert_eq!(recent.len(), RECENT_LIMIT);
+    assert_eq!(recent[0], (String::from("addst"), b"string 6".to_vec()));
+    assert_eq!(recent[RECENT_LIMIT - 1].1.len(), RECENT_KEY_LIMIT);
+
+    let report = render("signal SIGSEGV", "frames", &recent);
+    assert!(report.contains("reason: signal SIGSEGV\n"));
+    assert!(report.contains("\nhooks:\n"));
+    assert!(report.contains("\n  [addst] string 36\n"));
+    assert!(report.ends_with("backtrace:\nframes"));
+    assert!(report_path().file_name().unwrap().to_str().unwrap().starts_with("crash-"));
+  }
+}
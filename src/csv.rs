@@ -0,0 +1,244 @@
+// Minimal RFC 4180 reader/writer working on raw bytes, dictionaries are not necessarily utf-8
+
+const QUOTE: u8 = b'"';
+const COMMA: u8 = b',';
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+pub struct Record {
+  pub line: usize,
+  pub fields: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CsvError {
+  pub line: usize,
+  pub message: String,
+}
+
+impl std::fmt::Display for CsvError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+pub struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  line: usize,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(data: &'a [u8]) -> Self {
+    let pos = if data.starts_with(BOM) { BOM.len() } else { 0 };
+    Self { data, pos, line: 1 }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.data.get(self.pos).copied()
+  }
+
+  fn bump(&mut self) -> Option<u8> {
+    let c = self.peek()?;
+    self.pos += 1;
+    if c == LF {
+      self.line += 1;
+    }
+    Some(c)
+  }
+
+  // consumes a record terminator, returns false if the next byte is something else
+  fn end_of_record(&mut self) -> bool {
+    match self.peek() {
+      None => true,
+      Some(LF) => {
+        self.bump();
+        true
+      }
+      Some(CR) if self.data.get(self.pos + 1) == Some(&LF) => {
+        self.pos += 1;
+        self.bump();
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // skips the rest of the physical line after an error so the next record can be parsed
+  fn recover(&mut self) {
+    while let Some(c) = self.bump() {
+      if c == LF {
+        break;
+      }
+    }
+  }
+
+  fn quoted(&mut self, start: usize) -> Result<Vec<u8>, CsvError> {
+    let mut field = Vec::new();
+    loop {
+      match self.bump() {
+        None => {
+          return Err(CsvError {
+            line: start,
+            message: String::from("unterminated quoted field"),
+          })
+        }
+        Some(QUOTE) if self.peek() == Some(QUOTE) => {
+          self.bump();
+          field.push(QUOTE);
+        }
+        Some(QUOTE) => return Ok(field),
+        Some(c) => field.push(c),
+      }
+    }
+  }
+
+  fn unquoted(&mut self) -> Result<Vec<u8>, CsvError> {
+    let mut field = Vec::new();
+    while let Some(c) = self.peek() {
+      match c {
+        COMMA | LF => break,
+        CR if self.data.get(self.pos + 1) == Some(&LF) => break,
+        QUOTE => {
+          return Err(CsvError {
+            line: self.line,
+            message: String::from("quote inside unquoted field"),
+          })
+        }
+        _ => {
+          self.bump();
+          field.push(c);
+        }
+      }
+    }
+    Ok(field)
+  }
+
+  fn record(&mut self) -> Result<Record, CsvError> {
+    let line = self.line;
+    let mut fields = Vec::new();
+    loop {
+      let field = match self.peek() {
+        Some(QUOTE) => {
+          self.bump();
+          self.quoted(self.line)?
+        }
+        _ => self.unquoted()?,
+      };
+      fields.push(field);
+      if self.peek() == Some(COMMA) {
+        self.bump();
+        continue;
+      }
+      if self.end_of_record() {
+        return Ok(Record { line, fields });
+      }
+      return Err(CsvError {
+        line: self.line,
+        message: format!("unexpected character {:?} after closing quote", self.peek().unwrap() as char),
+      });
+    }
+  }
+}
+
+impl Iterator for Reader<'_> {
+  type Item = Result<Record, CsvError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    // blank lines are not records
+    while self.end_of_record() {
+      self.peek()?;
+    }
+    let result = self.record();
+    if result.is_err() {
+      self.recover();
+    }
+    Some(result)
+  }
+}
+
+// always quotes, the same way dictionary.csv is written
+pub fn write_record(out: &mut Vec<u8>, fields: &[&[u8]]) {
+  for (i, field) in fields.iter().enumerate() {
+    if i > 0 {
+      out.push(COMMA);
+    }
+    out.push(QUOTE);
+    for &c in field.iter() {
+      if c == QUOTE {
+        out.push(QUOTE);
+      }
+      out.push(c);
+    }
+    out.push(QUOTE);
+  }
+  out.push(LF);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  type Parsed = Result<(usize, Vec<Vec<u8>>), CsvError>;
+
+  fn parse(data: &[u8]) -> Vec<Parsed> {
+    Reader::new(data).map(|r| r.map(|r| (r.line, r.fields))).collect()
+  }
+
+  fn ok(line: usize, fields: &[&[u8]]) -> Parsed {
+    Ok((line, fields.iter().map(|f| f.to_vec()).collect()))
+  }
+
+  #[test]
+  fn quoted_and_unquoted_fields() {
+    assert_eq!(
+      parse(b"\"a\",\"b\"\r\nc,d\n\n\"e\",\n"),
+      vec![ok(1, &[b"a", b"b"]), ok(2, &[b"c", b"d"]), ok(4, &[b"e", b""])]
+    );
+  }
+
+  #[test]
+  fn escaped_quotes_commas_and_newlines() {
+    assert_eq!(
+      parse(b"\"say \"\"hi\"\"\",\"a\"\",\"\"b\"\n\"two\nlines\",x\n\"next\",y"),
+      vec![
+        ok(1, &[b"say \"hi\"", b"a\",\"b"]),
+        ok(2, &[b"two\nlines", b"x"]),
+        ok(4, &[b"next", b"y"]),
+      ]
+    );
+  }
+
+  #[test]
+  fn malformed_rows_are_reported_and_skipped() {
+    let result = parse(b"\"a\"x,\"b\"\nc\"d,e\n\"ok\",\"ok\"\n\"never closed,x\n");
+    assert_eq!(result[0].as_ref().unwrap_err().line, 1);
+    assert_eq!(result[1].as_ref().unwrap_err().line, 2);
+    assert_eq!(result[2], ok(3, &[b"ok", b"ok"]));
+    assert_eq!(
+      result[3],
+      Err(CsvError {
+        line: 4,
+        message: String::from("unterminated quoted field")
+      })
+    );
+    assert_eq!(result.len(), 4);
+  }
+
+  #[test]
+  fn bom_is_skipped() {
+    assert_eq!(parse(b"\xef\xbb\xbf\"a\",\"b\""), vec![ok(1, &[b"a", b"b"])]);
+  }
+
+  #[test]
+  fn written_records_round_trip() {
+    let mut out = Vec::new();
+    write_record(&mut out, &[b"with \"quote\", comma", b"multi\nline"]);
+    write_record(&mut out, &[b"\xe0\xe1", b""]);
+    assert_eq!(
+      parse(&out),
+      vec![ok(1, &[b"with \"quote\", comma", b"multi\nline"]), ok(3, &[b"\xe0\xe1", b""])]
+    );
+  }
+}
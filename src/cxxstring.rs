@@ -1,291 +1,959 @@
 #![allow(dead_code)]
 
-use std::alloc::{alloc_zeroed, realloc, Layout};
-use std::ops::{Index, IndexMut};
-
 #[cfg(target_os = "linux")]
-#[repr(C)]
-pub struct CxxString {
-  pub ptr: *mut u8,
-  pub len: usize,
-  pub sso: CxxSSO,
+use dlopen2::raw::Library;
+use std::marker::PhantomData;
+use std::ops::{Bound, Index, IndexMut, RangeBounds};
+
+use crate::config::CONFIG;
+#[cfg(target_os = "windows")]
+use crate::utils;
+
+// Buffers of grown strings are freed by the game, so they have to come from the game's operator new.
+struct Allocator {
+  new: unsafe extern "C" fn(usize) -> *mut u8,
+  delete: unsafe extern "C" fn(*mut u8),
 }
 
-#[cfg(target_os = "linux")]
-#[repr(C)]
-pub union CxxSSO {
-  pub capa: usize,
-  pub buf: [u8; 16],
+#[static_init::dynamic(lazy)]
+static ALLOCATOR: Option<Allocator> = unsafe {
+  let allocator = Allocator::resolve();
+  // tests run without a game, and without libstdc++ unless the shim is built
+  #[cfg(test)]
+  let allocator = allocator.or_else(|| Some(Allocator::heap()));
+  if allocator.is_none() {
+    log::error!("unable to find the game's operator new and delete, strings will not grow");
+  }
+  allocator
+};
+
+impl Allocator {
+  // the global scope finds the operator new the game links to, even when it brings its own
+  #[cfg(target_os = "linux")]
+  unsafe fn resolve() -> Option<Self> {
+    let lib = Library::open_self().ok()?;
+    Some(Self {
+      new: lib.symbol("_Znwm").ok()?,
+      delete: lib.symbol("_ZdlPv").ok()?,
+    })
+  }
+
+  // Rust's heap with the size kept in front of the block, nothing but the tests frees these buffers
+  #[cfg(test)]
+  fn heap() -> Self {
+    const HEADER: usize = 16;
+    unsafe extern "C" fn new(size: usize) -> *mut u8 {
+      let block = std::alloc::alloc(std::alloc::Layout::from_size_align_unchecked(size + HEADER, HEADER));
+      (block as *mut usize).write(size);
+      block.add(HEADER)
+    }
+    unsafe extern "C" fn delete(ptr: *mut u8) {
+      let block = ptr.sub(HEADER);
+      let size = (block as *const usize).read();
+      std::alloc::dealloc(block, std::alloc::Layout::from_size_align_unchecked(size + HEADER, HEADER));
+    }
+    Self { new, delete }
+  }
+
+  // only by offset: guessing at the CRT the game uses would mix heaps again when it links the CRT statically
+  #[cfg(target_os = "windows")]
+  unsafe fn resolve() -> Option<Self> {
+    let offsets = CONFIG.offset.as_ref()?;
+    let (new, delete) = (offsets.operator_new?, offsets.operator_delete?);
+    Some(Self {
+      new: std::mem::transmute::<usize, unsafe extern "C" fn(usize) -> *mut u8>(utils::address(new)),
+      delete: std::mem::transmute::<usize, unsafe extern "C" fn(*mut u8)>(utils::address(delete)),
+    })
+  }
+}
+
+// The std::string layout of the C++ library the game was built with. Everything works on the address of a
+// string object, the views below are the only way hooks get at one.
+pub trait CxxString: Sync {
+  fn name(&self) -> &'static str;
+
+  unsafe fn len(&self, s: *const u8) -> usize;
+
+  // short strings are kept in the object itself
+  unsafe fn is_local(&self, s: *const u8) -> bool;
+
+  unsafe fn capacity(&self, s: *const u8) -> usize;
+
+  unsafe fn data(&self, s: *const u8) -> *mut u8;
+
+  // grows the buffer the way the library does, false when there is no allocator
+  unsafe fn reserve(&self, s: *mut u8, capacity: usize) -> bool;
+
+  // stores len only, the terminating NUL is written by set_len
+  unsafe fn set_size(&self, s: *mut u8, len: usize);
+
+  // makes the uninitialized object at s view the NUL-terminated ptr, short strings are copied
+  unsafe fn borrow(&self, s: *mut u8, ptr: *const u8, len: usize);
+}
+
+impl dyn CxxString {
+  // passes f a std::string viewing ptr, which has to be NUL-terminated and outlive the call, like a Translation
+  pub unsafe fn with_borrowed<R>(&self, ptr: *const u8, len: usize, f: impl FnOnce(*const u8) -> R) -> R {
+    let mut object = [0usize; 4];
+    let s = object.as_mut_ptr() as *mut u8;
+    self.borrow(s, ptr, len);
+    f(s)
+  }
+
+  unsafe fn set_len(&self, s: *mut u8, len: usize) -> bool {
+    if !self.reserve(s, len) {
+      return false;
+    }
+    *self.data(s).add(len) = 0;
+    self.set_size(s, len);
+    true
+  }
+}
+
+#[static_init::dynamic(lazy)]
+pub static LAYOUT: &'static dyn CxxString = layout();
+
+fn by_name(name: &str) -> Option<&'static dyn CxxString> {
+  match name {
+    "libstdc++" => Some(&LibStdCxx),
+    "libc++" => Some(&LibCxx),
+    "msvc" => Some(&Msvc),
+    _ => None,
+  }
 }
 
 #[cfg(target_os = "linux")]
-impl CxxString {
-  pub unsafe fn new<T>(ptr: *mut T, size: usize) -> Self {
-    if size >= 16 {
-      return Self {
-        ptr: ptr as *mut u8,
-        len: size,
-        sso: CxxSSO { capa: size },
-      };
-    }
-    let array_ptr: *const [u8; 16] = ptr as *const [u8; 16];
-    Self {
-      ptr: ptr as *mut u8,
-      len: size,
-      sso: CxxSSO {
-        buf: std::mem::transmute(*array_ptr),
-      },
-    }
-  }
-
-  pub unsafe fn resize(&mut self, size: usize) {
-    const MAGIC_CAPA: usize = 1099511627775;
-
-    if size > self.len {
-      if size >= 16 {
-        // FIXME: magic number here 32^8 - all 8 chars are spaces
-        // if capa is to big, then we assume it is not actual capa but buffer value from stack
-        // so this logic branch should be first allocation
-        if self.len < 16 && self.sso.capa > MAGIC_CAPA {
-          let new_array = alloc_zeroed(Layout::array::<u8>(30).unwrap());
-          std::ptr::copy_nonoverlapping(self.sso.buf.as_ptr(), new_array, 16);
-          self.ptr = new_array;
-          self.sso.capa = 30;
-        } else if self.len >= 16 && size > self.sso.capa {
-          let new_size = self.sso.capa * 2;
-          self.ptr = realloc(self.ptr, Layout::array::<u8>(self.sso.capa).unwrap(), new_size);
-          self.sso.capa = new_size;
-        }
-      }
-    } else {
-      // TODO: not tested, not use in 0.2.1
-      if self.sso.capa >= 16 && self.sso.capa < MAGIC_CAPA {
-        let target = self.ptr as usize + size;
-        let slice = std::slice::from_raw_parts_mut(target as *mut u8, 1);
-        slice[0] = 0;
-      } else {
-        self.sso.buf[size] = 0;
-      }
+fn probe() -> Option<&'static str> {
+  crate::symbols::string_layout()
+}
+
+#[cfg(target_os = "windows")]
+fn probe() -> Option<&'static str> {
+  Some("msvc")
+}
+
+// metadata.string_layout of the offsets when given, otherwise what the game links to
+fn layout() -> &'static dyn CxxString {
+  if let Some(name) = CONFIG.offset_metadata.as_ref().and_then(|m| m.string_layout.as_deref()) {
+    match by_name(name) {
+      Some(layout) => return layout,
+      None => log::error!("unknown string layout {name} in offsets, probing the game instead"),
     }
-    self.len = size;
   }
+  probe().and_then(by_name).unwrap_or_else(|| {
+    log::warn!("unable to tell the game's C++ library, assuming libstdc++");
+    &LibStdCxx
+  })
+}
 
-  pub unsafe fn from_ptr(ptr: *const u8) -> &'static mut Self {
-    std::mem::transmute(ptr)
+// GCC: pointer, size, then the capacity or a 16 byte buffer the pointer points to
+pub struct LibStdCxx;
+
+#[repr(C)]
+struct LibStdCxxRep {
+  ptr: *mut u8,
+  len: usize,
+  sso: LibStdCxxSSO,
+}
+
+#[repr(C)]
+union LibStdCxxSSO {
+  capa: usize,
+  buf: [u8; 16],
+}
+
+impl CxxString for LibStdCxx {
+  fn name(&self) -> &'static str {
+    "libstdc++"
   }
 
-  pub unsafe fn as_ptr(&mut self) -> *const u8 {
-    std::mem::transmute(self)
+  unsafe fn len(&self, s: *const u8) -> usize {
+    (*(s as *const LibStdCxxRep)).len
   }
 
-  pub unsafe fn as_mut_ptr(&mut self) -> *mut u8 {
-    std::mem::transmute(self)
+  unsafe fn is_local(&self, s: *const u8) -> bool {
+    let rep = &*(s as *const LibStdCxxRep);
+    std::ptr::eq(rep.ptr, rep.sso.buf.as_ptr())
   }
 
-  pub unsafe fn to_str(&mut self) -> Result<&'static str, Box<dyn std::error::Error>> {
-    match std::ffi::CStr::from_bytes_with_nul(std::slice::from_raw_parts(self.ptr, self.len + 1)) {
-      Ok(value) => match value.to_str() {
-        Ok(value) => Ok(value),
-        Err(err) => Err(err.into()),
-      },
-      Err(err) => Err(err.into()),
+  unsafe fn capacity(&self, s: *const u8) -> usize {
+    match self.is_local(s) {
+      true => 15,
+      false => (*(s as *const LibStdCxxRep)).sso.capa,
     }
   }
 
-  pub unsafe fn to_bytes_without_nul(&mut self) -> &[u8] {
-    std::slice::from_raw_parts(self.ptr, self.len)
+  unsafe fn data(&self, s: *const u8) -> *mut u8 {
+    (*(s as *const LibStdCxxRep)).ptr
   }
 
-  pub fn size(&self) -> usize {
-    self.len
+  // like std::string::_M_create
+  unsafe fn reserve(&self, s: *mut u8, capacity: usize) -> bool {
+    let old = self.capacity(s);
+    if capacity <= old {
+      return true;
+    }
+    let Some(allocator) = ALLOCATOR.as_ref() else {
+      return false;
+    };
+    let capacity = std::cmp::max(capacity, 2 * old);
+    let local = self.is_local(s);
+    let rep = &mut *(s as *mut LibStdCxxRep);
+    let buf = (allocator.new)(capacity + 1);
+    std::ptr::copy_nonoverlapping(rep.ptr, buf, rep.len + 1);
+    if !local {
+      (allocator.delete)(rep.ptr);
+    }
+    rep.ptr = buf;
+    rep.sso.capa = capacity;
+    true
   }
 
-  pub unsafe fn pop_back(&mut self) {
-    let index = self.len;
-    self[index] = 0;
-    self.resize(self.len - 1);
+  unsafe fn set_size(&self, s: *mut u8, len: usize) {
+    (*(s as *mut LibStdCxxRep)).len = len;
   }
 
-  pub unsafe fn push_back(&mut self, symbol: u8) {
-    let index = self.len;
-    self.resize(index + 1);
-    self[index] = symbol;
+  unsafe fn borrow(&self, s: *mut u8, ptr: *const u8, len: usize) {
+    let rep = &mut *(s as *mut LibStdCxxRep);
+    rep.len = len;
+    match len > 15 {
+      true => {
+        rep.ptr = ptr as *mut u8;
+        rep.sso.capa = len;
+      }
+      false => {
+        rep.ptr = rep.sso.buf.as_mut_ptr();
+        std::ptr::copy_nonoverlapping(ptr, rep.ptr, len);
+        *rep.ptr.add(len) = 0;
+      }
+    }
   }
 }
 
-#[cfg(target_os = "linux")]
-impl Index<usize> for CxxString {
-  type Output = u8;
+// clang: the lowest bit of the first byte tells the long form, capacity with that bit, size and pointer,
+// from the short form, size shifted left by one and a 23 byte buffer
+pub struct LibCxx;
 
-  fn index(&self, index: usize) -> &Self::Output {
-    unsafe {
-      let mut data: *const u8 = self.sso.buf.as_ptr();
-      if self.sso.capa >= 16 {
-        data = self.ptr;
-      }
-      let target = data as usize + index;
-      let slice = std::slice::from_raw_parts(target as *const u8, 1);
-      &slice[0]
+#[repr(C)]
+struct LibCxxRep {
+  cap: usize,
+  len: usize,
+  ptr: *mut u8,
+}
+
+impl LibCxx {
+  // like basic_string::__recommend, allocations come in multiples of 16
+  fn recommend(capacity: usize) -> usize {
+    match capacity < 23 {
+      true => 22,
+      false => ((capacity + 16) & !15) - 1,
     }
   }
 }
 
-#[cfg(target_os = "linux")]
-impl IndexMut<usize> for CxxString {
-  fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-    unsafe {
-      let mut data: *mut u8 = self.sso.buf.as_mut_ptr();
-      if self.sso.capa >= 16 {
-        data = self.ptr;
+impl CxxString for LibCxx {
+  fn name(&self) -> &'static str {
+    "libc++"
+  }
+
+  unsafe fn len(&self, s: *const u8) -> usize {
+    match self.is_local(s) {
+      true => (*s >> 1) as usize,
+      false => (*(s as *const LibCxxRep)).len,
+    }
+  }
+
+  unsafe fn is_local(&self, s: *const u8) -> bool {
+    *s & 1 == 0
+  }
+
+  unsafe fn capacity(&self, s: *const u8) -> usize {
+    match self.is_local(s) {
+      true => 22,
+      false => ((*(s as *const LibCxxRep)).cap & !1) - 1,
+    }
+  }
+
+  unsafe fn data(&self, s: *const u8) -> *mut u8 {
+    match self.is_local(s) {
+      true => s.add(1) as *mut u8,
+      false => (*(s as *const LibCxxRep)).ptr,
+    }
+  }
+
+  // like basic_string::__grow_by, long strings never go back to the short form
+  unsafe fn reserve(&self, s: *mut u8, capacity: usize) -> bool {
+    let old = self.capacity(s);
+    if capacity <= old {
+      return true;
+    }
+    let Some(allocator) = ALLOCATOR.as_ref() else {
+      return false;
+    };
+    let capacity = Self::recommend(std::cmp::max(capacity, 2 * old));
+    let (data, len) = (self.data(s), self.len(s));
+    let buf = (allocator.new)(capacity + 1);
+    std::ptr::copy_nonoverlapping(data, buf, len + 1);
+    if !self.is_local(s) {
+      (allocator.delete)(data);
+    }
+    *(s as *mut LibCxxRep) = LibCxxRep {
+      cap: (capacity + 1) | 1,
+      len,
+      ptr: buf,
+    };
+    true
+  }
+
+  unsafe fn set_size(&self, s: *mut u8, len: usize) {
+    match self.is_local(s) {
+      true => *s = (len << 1) as u8,
+      false => (*(s as *mut LibCxxRep)).len = len,
+    }
+  }
+
+  unsafe fn borrow(&self, s: *mut u8, ptr: *const u8, len: usize) {
+    match len > 22 {
+      true => {
+        *(s as *mut LibCxxRep) = LibCxxRep {
+          cap: (len + 2) | 1,
+          len,
+          ptr: ptr as *mut u8,
+        }
+      }
+      false => {
+        *s = (len << 1) as u8;
+        std::ptr::copy_nonoverlapping(ptr, s.add(1), len);
+        *s.add(1 + len) = 0;
       }
-      let target = data as usize + index;
-      let slice = std::slice::from_raw_parts_mut(target as *mut u8, 1);
-      &mut slice[0]
     }
   }
 }
 
-#[cfg(target_os = "windows")]
+// MSVC: a 16 byte buffer or the pointer, size, capacity
+pub struct Msvc;
+
 #[repr(C)]
-pub struct CxxString {
-  pub data: CxxStringContent,
-  pub len: usize,
-  pub capa: usize,
+struct MsvcRep {
+  data: MsvcContent,
+  len: usize,
+  capa: usize,
 }
 
-#[cfg(target_os = "windows")]
 #[repr(C)]
-pub union CxxStringContent {
-  pub buf: [u8; 16],
-  pub ptr: *mut u8,
+union MsvcContent {
+  buf: [u8; 16],
+  ptr: *mut u8,
 }
 
-#[cfg(target_os = "windows")]
-impl CxxString {
-  pub unsafe fn new<T>(ptr: *mut T, size: usize) -> Self {
-    if size >= 16 {
-      return Self {
-        data: CxxStringContent { ptr: ptr as *mut u8 },
-        len: size,
-        capa: size,
-      };
-    }
-    let array_ptr: *const [u8; 16] = ptr as *const [u8; 16];
-    Self {
-      data: CxxStringContent {
-        buf: std::mem::transmute(*array_ptr),
-      },
-      len: size,
-      capa: 15,
-    }
-  }
-
-  pub unsafe fn resize(&mut self, size: usize) {
-    if size > self.len {
-      match (size >= 16, self.capa) {
-        (true, v) if v == 15 => {
-          let new_array = alloc_zeroed(Layout::array::<u8>(32).unwrap());
-          std::ptr::copy_nonoverlapping(self.data.buf.as_ptr(), new_array, 16);
-          self.data.ptr = new_array;
-          self.capa = 32;
-        }
-        (true, v) if v < size => {
-          self.data.ptr = realloc(self.data.ptr, Layout::array::<u8>(self.capa).unwrap(), self.capa + 16);
-          self.capa += 16;
-        }
-        (_, _) => (),
+impl CxxString for Msvc {
+  fn name(&self) -> &'static str {
+    "msvc"
+  }
+
+  unsafe fn len(&self, s: *const u8) -> usize {
+    (*(s as *const MsvcRep)).len
+  }
+
+  // MSVC's own test for large mode, the buffer pointer only exists then
+  unsafe fn is_local(&self, s: *const u8) -> bool {
+    (*(s as *const MsvcRep)).capa < 16
+  }
+
+  unsafe fn capacity(&self, s: *const u8) -> usize {
+    (*(s as *const MsvcRep)).capa
+  }
+
+  unsafe fn data(&self, s: *const u8) -> *mut u8 {
+    match self.is_local(s) {
+      true => s as *mut u8,
+      false => (*(s as *const MsvcRep)).data.ptr,
+    }
+  }
+
+  // Like std::string::_Calculate_growth. MSVC allocates 4096 bytes and more with an alignment header the
+  // game's delete expects, so strings stop short of that.
+  unsafe fn reserve(&self, s: *mut u8, capacity: usize) -> bool {
+    let (local, data) = (self.is_local(s), self.data(s));
+    let rep = &mut *(s as *mut MsvcRep);
+    if capacity <= rep.capa {
+      return true;
+    }
+    let capacity = std::cmp::max(capacity | 15, rep.capa + rep.capa / 2);
+    let Some(allocator) = ALLOCATOR.as_ref().filter(|_| capacity < 4095) else {
+      return false;
+    };
+    let buf = (allocator.new)(capacity + 1);
+    std::ptr::copy_nonoverlapping(data, buf, rep.len + 1);
+    if !local {
+      (allocator.delete)(rep.data.ptr);
+    }
+    rep.data.ptr = buf;
+    rep.capa = capacity;
+    true
+  }
+
+  unsafe fn set_size(&self, s: *mut u8, len: usize) {
+    (*(s as *mut MsvcRep)).len = len;
+  }
+
+  unsafe fn borrow(&self, s: *mut u8, ptr: *const u8, len: usize) {
+    let rep = &mut *(s as *mut MsvcRep);
+    rep.len = len;
+    match len > 15 {
+      true => {
+        rep.data.ptr = ptr as *mut u8;
+        rep.capa = len;
       }
-    } else {
-      if self.capa >= 16 {
-        let target = self.data.ptr as usize + size;
-        let slice = std::slice::from_raw_parts_mut(target as *mut u8, 1);
-        slice[0] = 0;
-      } else {
-        self.data.buf[size] = 0;
+      false => {
+        std::ptr::copy_nonoverlapping(ptr, s, len);
+        *s.add(len) = 0;
+        rep.capa = 15;
       }
     }
-    self.len = size;
   }
+}
 
-  pub unsafe fn from_ptr(ptr: *const u8) -> &'static mut Self {
-    std::mem::transmute(ptr)
+// A std::string of the game, borrowed for the duration of a hook call. The view only exists inside the closure
+// it is handed to, so it cannot outlive the call; only getting one is unsafe.
+#[derive(Clone, Copy)]
+pub struct CxxStrRef<'a> {
+  s: *const u8,
+  layout: &'static dyn CxxString,
+  _string: PhantomData<&'a [u8]>,
+}
+
+impl<'a> CxxStrRef<'a> {
+  // ptr has to point to a live std::string nobody changes while f runs
+  pub unsafe fn with<R>(ptr: *const u8, f: impl FnOnce(CxxStrRef<'_>) -> R) -> R {
+    Self::with_layout(ptr, *LAYOUT, f)
   }
 
-  pub unsafe fn to_str(&mut self) -> Result<&'static str, Box<dyn std::error::Error>> {
-    let mut data: *const u8 = self.data.buf.as_ptr();
-    if self.capa >= 16 {
-      data = self.data.ptr;
-    }
-    match std::ffi::CStr::from_bytes_with_nul(std::slice::from_raw_parts(data, self.len + 1)) {
-      Ok(value) => match value.to_str() {
-        Ok(value) => Ok(value),
-        Err(err) => Err(err.into()),
-      },
-      Err(err) => Err(err.into()),
-    }
+  pub unsafe fn with_layout<R>(
+    ptr: *const u8,
+    layout: &'static dyn CxxString,
+    f: impl FnOnce(CxxStrRef<'_>) -> R,
+  ) -> R {
+    f(Self::new(ptr, layout))
   }
 
-  pub unsafe fn to_bytes_without_nul(&mut self) -> &[u8] {
-    let mut data: *const u8 = self.data.buf.as_ptr();
-    if self.capa >= 16 {
-      data = self.data.ptr;
+  unsafe fn new(ptr: *const u8, layout: &'static dyn CxxString) -> Self {
+    Self {
+      s: ptr,
+      layout,
+      _string: PhantomData,
     }
-    std::slice::from_raw_parts(data, self.len)
   }
 
-  pub unsafe fn as_ptr(&mut self) -> *const u8 {
-    std::mem::transmute(self)
+  pub fn as_bytes(self) -> &'a [u8] {
+    unsafe { std::slice::from_raw_parts(self.layout.data(self.s), self.len()) }
   }
 
-  pub unsafe fn as_mut_ptr(&mut self) -> *mut u8 {
-    std::mem::transmute(self)
+  pub fn to_str(self) -> Result<&'a str, std::str::Utf8Error> {
+    std::str::from_utf8(self.as_bytes())
   }
 
-  pub fn size(&self) -> usize {
-    self.len
+  pub fn len(self) -> usize {
+    unsafe { self.layout.len(self.s) }
   }
 
-  pub unsafe fn pop_back(&mut self) {
-    let index = self.len;
-    self[index] = 0;
-    self.resize(self.len - 1);
+  pub fn is_empty(self) -> bool {
+    self.len() == 0
   }
 
-  pub unsafe fn push_back(&mut self, symbol: u8) {
-    let index = self.len;
-    self.resize(index + 1);
-    self[index] = symbol;
+  pub fn capacity(self) -> usize {
+    unsafe { self.layout.capacity(self.s) }
+  }
+
+  pub fn is_local(self) -> bool {
+    unsafe { self.layout.is_local(self.s) }
   }
 }
 
-#[cfg(target_os = "windows")]
-impl Index<usize> for CxxString {
+impl Index<usize> for CxxStrRef<'_> {
   type Output = u8;
 
   fn index(&self, index: usize) -> &Self::Output {
+    &self.as_bytes()[index]
+  }
+}
+
+// A std::string of the game the hook may edit in place. Growing can fail when the game's allocator was not
+// found, the string is then left as it was.
+pub struct CxxStrMut<'a> {
+  s: *mut u8,
+  layout: &'static dyn CxxString,
+  _string: PhantomData<&'a mut [u8]>,
+}
+
+impl CxxStrMut<'_> {
+  // ptr has to point to a live std::string nobody else uses while f runs
+  pub unsafe fn with<R>(ptr: *mut u8, f: impl FnOnce(&mut CxxStrMut<'_>) -> R) -> R {
+    Self::with_layout(ptr, *LAYOUT, f)
+  }
+
+  pub unsafe fn with_layout<R>(
+    ptr: *mut u8,
+    layout: &'static dyn CxxString,
+    f: impl FnOnce(&mut CxxStrMut<'_>) -> R,
+  ) -> R {
+    f(&mut CxxStrMut {
+      s: ptr,
+      layout,
+      _string: PhantomData,
+    })
+  }
+
+  pub fn as_ref(&self) -> CxxStrRef<'_> {
+    unsafe { CxxStrRef::new(self.s, self.layout) }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    self.as_ref().as_bytes()
+  }
+
+  pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(self.layout.data(self.s), self.len()) }
+  }
+
+  pub fn len(&self) -> usize {
+    self.as_ref().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn push(&mut self, byte: u8) -> bool {
+    self.extend_from_slice(&[byte])
+  }
+
+  pub fn pop(&mut self) -> Option<u8> {
+    let last = *self.as_bytes().last()?;
+    self.truncate(self.len() - 1);
+    Some(last)
+  }
+
+  pub fn extend_from_slice(&mut self, bytes: &[u8]) -> bool {
+    let len = self.len();
+    self.replace_range(len..len, bytes)
+  }
+
+  pub fn truncate(&mut self, len: usize) {
+    if len < self.len() {
+      unsafe { self.layout.set_len(self.s, len) };
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.truncate(0);
+  }
+
+  // panics when the range is out of bounds, like Vec::splice
+  pub fn replace_range(&mut self, range: impl RangeBounds<usize>, with: &[u8]) -> bool {
+    let len = self.len();
+    let start = match range.start_bound() {
+      Bound::Included(&start) => start,
+      Bound::Excluded(&start) => start + 1,
+      Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+      Bound::Included(&end) => end + 1,
+      Bound::Excluded(&end) => end,
+      Bound::Unbounded => len,
+    };
+    assert!(start <= end && end <= len, "range {start}..{end} out of bounds for length {len}");
+    let new_len = len - (end - start) + with.len();
     unsafe {
-      let mut data: *const u8 = self.data.buf.as_ptr();
-      if self.capa >= 16 {
-        data = self.data.ptr;
+      if !self.layout.reserve(self.s, new_len) {
+        return false;
       }
-      let target = data as usize + index;
-      let slice = std::slice::from_raw_parts(target as *const u8, 1);
-      &slice[0]
+      let data = self.layout.data(self.s);
+      std::ptr::copy(data.add(end), data.add(start + with.len()), len - end);
+      std::ptr::copy_nonoverlapping(with.as_ptr(), data.add(start), with.len());
+      self.layout.set_len(self.s, new_len)
     }
   }
 }
 
-#[cfg(target_os = "windows")]
-impl IndexMut<usize> for CxxString {
+impl Index<usize> for CxxStrMut<'_> {
+  type Output = u8;
+
+  fn index(&self, index: usize) -> &Self::Output {
+    &self.as_bytes()[index]
+  }
+}
+
+impl IndexMut<usize> for CxxStrMut<'_> {
   fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-    unsafe {
-      let mut data: *mut u8 = self.data.buf.as_mut_ptr();
-      if self.capa >= 16 {
-        data = self.data.ptr;
+    &mut self.as_bytes_mut()[index]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::Translation;
+
+  fn text(len: usize) -> Vec<u8> {
+    (0..len).map(|i| b'a' + (i % 26) as u8).collect()
+  }
+
+  // needs a C++ compiler, run with --features shim-tests
+  #[cfg(all(target_os = "linux", feature = "shim-tests"))]
+  mod libstdcxx {
+    use super::*;
+
+    #[repr(C)]
+    struct StdString {
+      _private: [u8; 0],
+    }
+
+    extern "C" {
+      fn shim_string_sizeof() -> usize;
+      fn shim_string_new(data: *const u8, len: usize) -> *mut StdString;
+      fn shim_string_delete(s: *mut StdString);
+      fn shim_string_data(s: *const StdString) -> *const u8;
+      fn shim_string_size(s: *const StdString) -> usize;
+      fn shim_string_capacity(s: *const StdString) -> usize;
+      fn shim_string_push_back(s: *mut StdString, c: u8);
+      fn shim_string_pop_back(s: *mut StdString);
+    }
+
+    // what C++ sees through the same object
+    unsafe fn native(s: *const StdString) -> (Vec<u8>, usize) {
+      let data = std::slice::from_raw_parts(shim_string_data(s), shim_string_size(s) + 1);
+      (data.to_vec(), shim_string_capacity(s))
+    }
+
+    unsafe fn view<R>(s: *mut StdString, f: impl FnOnce(&mut CxxStrMut<'_>) -> R) -> R {
+      CxxStrMut::with_layout(s as *mut u8, &LibStdCxx, f)
+    }
+
+    #[test]
+    fn the_test_binary_links_libstdcxx() {
+      assert_eq!(crate::symbols::string_layout(), Some("libstdc++"));
+      assert_eq!(by_name("libstdc++").unwrap().name(), "libstdc++");
+      assert!(by_name("stlport").is_none());
+    }
+
+    #[test]
+    fn layout_matches_std_string() {
+      assert_eq!(unsafe { shim_string_sizeof() }, std::mem::size_of::<LibStdCxxRep>());
+      for len in 0..40 {
+        unsafe {
+          let s = shim_string_new(text(len).as_ptr(), len);
+          CxxStrRef::with_layout(s as *const u8, &LibStdCxx, |cxx| {
+            assert_eq!(cxx.len(), len);
+            assert_eq!(cxx.is_empty(), len == 0);
+            assert_eq!(cxx.is_local(), len <= 15, "{len}");
+            assert_eq!(cxx.capacity(), shim_string_capacity(s), "{len}");
+            assert_eq!(cxx.as_bytes(), text(len).as_slice());
+            assert_eq!(cxx.to_str().unwrap().as_bytes(), text(len).as_slice());
+            if len > 0 {
+              assert_eq!(cxx[len / 2], *shim_string_data(s).add(len / 2));
+            }
+          });
+          shim_string_delete(s);
+        }
+      }
+    }
+
+    #[test]
+    fn growing_across_the_sso_boundary_matches_libstdcxx() {
+      for len in 13..18 {
+        unsafe {
+          let ours = shim_string_new(text(len).as_ptr(), len);
+          let theirs = shim_string_new(text(len).as_ptr(), len);
+          view(ours, |cxx| {
+            for i in 0..20 {
+              assert!(cxx.push(b'0' + i));
+              shim_string_push_back(theirs, b'0' + i);
+              assert_eq!(native(ours), native(theirs), "{len} + {i}");
+              assert_eq!(cxx.as_ref().is_local(), cxx.len() <= 15);
+            }
+          });
+          // C++ keeps using and finally frees the buffer the hook allocated
+          shim_string_push_back(ours, b'!');
+          assert_eq!(view(ours, |cxx| cxx.len()), len + 21);
+          shim_string_delete(ours);
+          shim_string_delete(theirs);
+        }
+      }
+    }
+
+    #[test]
+    fn shrinking_keeps_the_buffer() {
+      unsafe {
+        let ours = shim_string_new(text(17).as_ptr(), 17);
+        let theirs = shim_string_new(text(17).as_ptr(), 17);
+        view(ours, |cxx| {
+          for i in 0..5 {
+            assert_eq!(cxx.pop(), Some(text(17)[16 - i]));
+            shim_string_pop_back(theirs);
+            assert_eq!(native(ours), native(theirs));
+          }
+          assert_eq!(cxx.len(), 12);
+          assert!(!cxx.as_ref().is_local());
+
+          cxx.truncate(3);
+          assert_eq!(native(ours).0, b"abc\0");
+          cxx.truncate(10);
+          assert_eq!(cxx.len(), 3);
+          assert!(cxx.extend_from_slice(&[b'x'; 37]));
+          assert_eq!(shim_string_size(ours), 40);
+          assert!(cxx.as_ref().capacity() >= 40);
+          cxx.clear();
+          assert_eq!(native(ours).0, b"\0");
+        });
+        shim_string_delete(ours);
+        shim_string_delete(theirs);
+
+        let empty = shim_string_new(std::ptr::null(), 0);
+        view(empty, |cxx| {
+          assert_eq!(cxx.pop(), None);
+          assert!(cxx.is_empty());
+        });
+        shim_string_delete(empty);
+      }
+    }
+
+    #[test]
+    fn ranges_are_replaced_in_place() {
+      unsafe {
+        let s = shim_string_new(b"Urist McMiner".as_ptr(), 13);
+        view(s, |cxx| {
+          assert!(cxx.replace_range(6..8, b"Mc"));
+          assert!(cxx.replace_range(..5, b"Kadol"));
+          assert!(cxx.replace_range(8.., b"Smith the Elder"));
+          assert_eq!(native(s).0, b"Kadol McSmith the Elder\0");
+          assert!(!cxx.as_ref().is_local());
+          assert!(cxx.replace_range(5..=12, b""));
+          assert_eq!(native(s).0, b"Kadol the Elder\0");
+          cxx[0] = b'k';
+          cxx.as_bytes_mut()[6] = b'T';
+          assert_eq!(cxx.as_ref().to_str().unwrap(), "kadol The Elder");
+        });
+        shim_string_delete(s);
+      }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn replacing_past_the_end_panics() {
+      unsafe {
+        let s = shim_string_new(b"Urist".as_ptr(), 5);
+        view(s, |cxx| cxx.replace_range(3..6, b""));
+      }
+    }
+
+    #[test]
+    fn translations_are_passed_as_strings() {
+      let layout: &dyn CxxString = &LibStdCxx;
+      for len in [0, 1, 15, 16, 40] {
+        let translation = Translation::new(&text(len));
+        unsafe {
+          layout.with_borrowed(translation.as_ptr(), translation.len(), |s| {
+            assert_eq!(CxxStrRef::with_layout(s, &LibStdCxx, |s| s.is_local()), len < 16);
+            let s = s as *const StdString;
+            assert_eq!(native(s).0, [text(len), vec![0]].concat());
+            assert_eq!(shim_string_size(s), len);
+            assert_eq!(shim_string_capacity(s), std::cmp::max(len, 15));
+          });
+        }
+      }
+    }
+  }
+
+  mod libcxx {
+    use super::*;
+
+    // libc++ std::string bytes on x86_64, short: size << 1 and the characters
+    fn short(text: &[u8]) -> [usize; 3] {
+      let mut words = [0usize; 3];
+      let bytes = unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, 24) };
+      bytes[0] = (text.len() << 1) as u8;
+      bytes[1..=text.len()].copy_from_slice(text);
+      words
+    }
+
+    #[test]
+    fn layout_matches_libcxx() {
+      let words = short(b"Urist");
+      assert_eq!(words[0].to_le_bytes(), [10, b'U', b'r', b'i', b's', b't', 0, 0]);
+      unsafe {
+        CxxStrRef::with_layout(words.as_ptr() as *const u8, &LibCxx, |s| {
+          assert!(s.is_local());
+          assert_eq!((s.len(), s.capacity()), (5, 22));
+          assert_eq!(s.to_str().unwrap(), "Urist");
+        });
+      }
+
+      // what new std::string(40, 'a') holds: allocation size 48 with the long bit, size, pointer
+      let heap = [text(40), vec![0; 8]].concat();
+      let words = [48 | 1, 40, heap.as_ptr() as usize];
+      unsafe {
+        CxxStrRef::with_layout(words.as_ptr() as *const u8, &LibCxx, |s| {
+          assert!(!s.is_local());
+          assert_eq!((s.len(), s.capacity()), (40, 47));
+          assert_eq!(s.as_bytes(), text(40).as_slice());
+        });
+      }
+    }
+
+    #[test]
+    fn growing_across_the_sso_boundary() {
+      let mut words = short(&text(21));
+      let ptr = words.as_mut_ptr() as *mut u8;
+      unsafe {
+        CxxStrMut::with_layout(ptr, &LibCxx, |s| {
+          assert!(s.push(b'!'));
+          assert!(s.as_ref().is_local());
+          assert_eq!(s.as_bytes(), [text(21), b"!".to_vec()].concat().as_slice());
+          assert!(s.push(b'?'));
+          assert!(!s.as_ref().is_local());
+          assert_eq!((s.len(), s.as_ref().capacity()), (23, 47));
+        });
+        assert_eq!(words[0], 49);
+        CxxStrMut::with_layout(ptr, &LibCxx, |s| {
+          assert!(s.extend_from_slice(&text(30)));
+          assert_eq!((s.len(), s.as_ref().capacity()), (53, 95));
+          assert_eq!(*s.as_ref().as_bytes().as_ptr().add(53), 0);
+          s.truncate(3);
+          assert!(!s.as_ref().is_local());
+          assert!(s.replace_range(1..2, b"BC"));
+          assert_eq!(s.as_ref().to_str().unwrap(), "aBCc");
+        });
+        assert_eq!(words[1], 4);
+        CxxStrMut::with_layout(ptr, &LibCxx, |s| {
+          s.clear();
+          assert!(s.is_empty());
+        });
+      }
+    }
+
+    #[test]
+    fn translations_are_passed_as_strings() {
+      let layout: &dyn CxxString = &LibCxx;
+      for len in [0, 5, 22, 23, 40] {
+        let translation = Translation::new(&text(len));
+        unsafe {
+          layout.with_borrowed(translation.as_ptr(), translation.len(), |s| {
+            CxxStrRef::with_layout(s, &LibCxx, |s| {
+              assert_eq!(s.is_local(), len <= 22);
+              assert!(s.capacity() >= len);
+              assert_eq!(s.as_bytes(), text(len).as_slice());
+              assert_eq!(*s.as_bytes().as_ptr().add(len), 0);
+            });
+          });
+        }
+      }
+    }
+  }
+
+  mod msvc {
+    use super::*;
+
+    // MSVC std::string: buffer or pointer, size, capacity
+    fn fixture(content: [u8; 16], len: usize, capa: usize) -> [usize; 4] {
+      let mut words = [0usize; 4];
+      words[0] = usize::from_le_bytes(content[..8].try_into().unwrap());
+      words[1] = usize::from_le_bytes(content[8..].try_into().unwrap());
+      words[2] = len;
+      words[3] = capa;
+      words
+    }
+
+    fn local(text: &[u8]) -> [usize; 4] {
+      let mut content = [0u8; 16];
+      content[..text.len()].copy_from_slice(text);
+      fixture(content, text.len(), 15)
+    }
+
+    #[test]
+    fn layout_matches_msvc() {
+      assert_eq!(std::mem::size_of::<MsvcRep>(), 32);
+      let words = local(b"Urist");
+      unsafe {
+        CxxStrRef::with_layout(words.as_ptr() as *const u8, &Msvc, |s| {
+          assert!(s.is_local());
+          assert_eq!((s.len(), s.capacity()), (5, 15));
+          assert_eq!(s.as_bytes(), b"Urist");
+          assert_eq!(s.to_str().unwrap(), "Urist");
+          assert_eq!(s[4], b't');
+          assert_eq!(s.as_bytes().get(5), None);
+        });
+      }
+
+      let heap = b"A very long string that does not fit\0".to_vec();
+      let mut content = [0u8; 16];
+      content[..8].copy_from_slice(&(heap.as_ptr() as usize).to_le_bytes());
+      let words = fixture(content, heap.len() - 1, 47);
+      unsafe {
+        CxxStrRef::with_layout(words.as_ptr() as *const u8, &Msvc, |s| {
+          assert!(!s.is_local());
+          assert_eq!(s.as_bytes(), &heap[..heap.len() - 1]);
+          assert_eq!(s[2], b'v');
+        });
+      }
+    }
+
+    #[test]
+    fn growing_across_the_sso_boundary() {
+      let mut words = local(b"fourteen chars");
+      unsafe {
+        CxxStrMut::with_layout(words.as_mut_ptr() as *mut u8, &Msvc, |s| {
+          assert!(s.push(b'!'));
+          assert!(s.as_ref().is_local());
+          assert!(s.push(b'?'));
+          assert!(!s.as_ref().is_local());
+          assert_eq!(s.as_ref().capacity(), 31);
+          assert_eq!(s.as_bytes(), b"fourteen chars!?");
+          assert!(s.extend_from_slice(&[b'.'; 16]));
+          assert_eq!((s.len(), s.as_ref().capacity()), (32, 47));
+          assert_eq!(s.pop(), Some(b'.'));
+          assert_eq!(s.len(), 31);
+          assert!(!s.as_ref().is_local());
+          s.truncate(2);
+          assert_eq!(s.as_bytes(), b"fo");
+          assert!(!s.extend_from_slice(&[b'.'; 5000]));
+          assert_eq!(s.len(), 2);
+          assert!(s.replace_range(1.., b"ortress"));
+          s[0] = b'F';
+          assert_eq!(s.as_ref().to_str().unwrap(), "Fortress");
+          s.clear();
+          assert!(s.is_empty());
+        });
+      }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn indexing_past_the_end_panics() {
+      let words = local(b"Urist");
+      // the NUL terminator is not part of the string
+      unsafe { CxxStrRef::with_layout(words.as_ptr() as *const u8, &Msvc, |s| s[5]) };
+    }
+
+    #[test]
+    fn translations_are_passed_as_strings() {
+      let layout: &dyn CxxString = &Msvc;
+      let short = Translation::new(b"Urist");
+      let long = Translation::new(b"A very long string that does not fit");
+      unsafe {
+        layout.with_borrowed(short.as_ptr(), short.len(), |s| {
+          CxxStrRef::with_layout(s, &Msvc, |s| {
+            assert!(s.is_local());
+            assert_eq!(s.as_bytes(), b"Urist");
+          });
+        });
+        layout.with_borrowed(long.as_ptr(), long.len(), |s| {
+          CxxStrRef::with_layout(s, &Msvc, |s| {
+            assert!(!s.is_local());
+            assert_eq!(s.as_bytes(), long.as_bytes());
+          });
+        });
       }
-      let target = data as usize + index;
-      let slice = std::slice::from_raw_parts_mut(target as *mut u8, 1);
-      &mut slice[0]
     }
   }
 }
@@ -0,0 +1,301 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG;
+
+pub const REPORT_FILE: &str = "dfint-dictionary-report.txt";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+  pub layer: usize,
+  pub line: usize,
+}
+
+struct Duplicate {
+  key: Vec<u8>,
+  context: String,
+  at: Location,
+  value: Vec<u8>,
+  previous_at: Location,
+  previous: Vec<u8>,
+}
+
+// Problems found while loading a dictionary, written next to the log so translators can fix the csv
+pub struct Diagnostics {
+  max_growth: f64,
+  glyphs: Option<[bool; 256]>,
+  layers: Vec<String>,
+  seen: HashMap<(Vec<u8>, String), (Location, Vec<u8>)>,
+  malformed: Vec<(Location, String)>,
+  duplicates: Vec<Duplicate>,
+  empty: Vec<(Location, Vec<u8>)>,
+  identical: Vec<(Location, Vec<u8>)>,
+  grown: Vec<(Location, Vec<u8>, usize, usize)>,
+  missing_glyphs: Vec<(Location, Vec<u8>, Vec<u8>)>,
+  // which layer won and what each layer added, kept in the compiled dictionary for the starts that skip the csv
+  notes: Vec<String>,
+}
+
+impl Diagnostics {
+  pub fn new(max_growth: f64, glyphs: Option<[bool; 256]>) -> Self {
+    Self {
+      max_growth,
+      glyphs,
+      layers: Vec::new(),
+      seen: HashMap::new(),
+      malformed: Vec::new(),
+      duplicates: Vec::new(),
+      empty: Vec::new(),
+      identical: Vec::new(),
+      grown: Vec::new(),
+      missing_glyphs: Vec::new(),
+      notes: Vec::new(),
+    }
+  }
+
+  pub fn from_config() -> Self {
+    Self::new(CONFIG.settings.dictionary_max_growth, CONFIG.encoding.glyphs())
+  }
+
+  pub fn layer(&mut self, name: &str) -> usize {
+    self.layers.push(String::from(name));
+    self.layers.len() - 1
+  }
+
+  pub fn location(&self, at: Location) -> String {
+    format!("{}:{}", self.layers.get(at.layer).map_or("?", |l| l.as_str()), at.line)
+  }
+
+  pub fn malformed(&mut self, at: Location, message: String) {
+    self.malformed.push((at, message));
+  }
+
+  pub fn empty(&mut self, at: Location, key: &[u8]) {
+    self.empty.push((at, key.to_vec()));
+  }
+
+  // called for every context of a row in load order, so the last duplicate is the one that wins;
+  // duplicates inside a layer are reported, an entry from an earlier layer is returned as overridden;
+  // entries for different contexts do not collide
+  pub fn entry(&mut self, at: Location, key: &[u8], context: &str, value: &[u8]) -> Option<Location> {
+    let mut overridden = None;
+    if let Some((previous_at, previous)) = self
+      .seen
+      .insert((key.to_vec(), String::from(context)), (at, value.to_vec()))
+    {
+      if previous_at.layer != at.layer {
+        overridden = Some(previous_at);
+      } else if previous != value {
+        self.duplicates.push(Duplicate {
+          key: key.to_vec(),
+          context: String::from(context),
+          at,
+          value: value.to_vec(),
+          previous_at,
+          previous,
+        });
+      }
+    }
+    overridden
+  }
+
+  pub fn note(&mut self, line: String) {
+    log::info!("{line}");
+    self.notes.push(line);
+  }
+
+  pub fn notes(&self) -> &[String] {
+    &self.notes
+  }
+
+  // checks on the translation itself, called once per row whatever contexts it lists
+  pub fn value(&mut self, at: Location, key: &[u8], value: &[u8]) {
+    if key == value {
+      self.identical.push((at, key.to_vec()));
+    }
+    if value.len() as f64 > key.len() as f64 * self.max_growth {
+      self.grown.push((at, key.to_vec(), key.len(), value.len()));
+    }
+    if let Some(glyphs) = &self.glyphs {
+      let mut missing: Vec<u8> = value.iter().copied().filter(|&c| !glyphs[c as usize]).collect();
+      missing.sort_unstable();
+      missing.dedup();
+      if !missing.is_empty() {
+        self.missing_glyphs.push((at, key.to_vec(), missing));
+      }
+    }
+  }
+
+  pub fn problems(&self) -> usize {
+    self.malformed.len()
+      + self.duplicates.len()
+      + self.empty.len()
+      + self.identical.len()
+      + self.grown.len()
+      + self.missing_glyphs.len()
+  }
+
+  pub fn summary(&self) -> String {
+    format!(
+      "malformed {}, conflicting duplicates {}, empty {}, identical {}, grown {}, missing glyphs {}",
+      self.malformed.len(),
+      self.duplicates.len(),
+      self.empty.len(),
+      self.identical.len(),
+      self.grown.len(),
+      self.missing_glyphs.len()
+    )
+  }
+
+  // keys and values are written as raw bytes, so the report opens in the same encoding as the csv
+  pub fn render(&self) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let _ = writeln!(
+      out,
+      "dictionary {}, {}",
+      self.layers.join(", "),
+      chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    let _ = writeln!(out, "{}", self.summary());
+
+    section(&mut out, "malformed rows", &self.malformed, |out, (at, message)| {
+      let _ = write!(out, "{}: {message}", self.location(*at));
+    });
+    section(
+      &mut out,
+      "duplicate keys with conflicting values",
+      &self.duplicates,
+      |out, d| {
+        let _ = write!(out, "{}: ", self.location(d.at));
+        quoted(out, &d.key);
+        if !d.context.is_empty() {
+          let _ = write!(out, " [{}]", d.context);
+        }
+        out.extend_from_slice(b" = ");
+        quoted(out, &d.value);
+        let _ = write!(out, " overrides {}: ", self.location(d.previous_at));
+        quoted(out, &d.previous);
+      },
+    );
+    section(&mut out, "empty translations", &self.empty, |out, (at, key)| {
+      let _ = write!(out, "{}: ", self.location(*at));
+      quoted(out, key);
+    });
+    section(&mut out, "translations identical to the key", &self.identical, |out, (at, key)| {
+      let _ = write!(out, "{}: ", self.location(*at));
+      quoted(out, key);
+    });
+    section(
+      &mut out,
+      &format!("translations longer than {}x the key", self.max_growth),
+      &self.grown,
+      |out, (at, key, from, to)| {
+        let _ = write!(out, "{}: ", self.location(*at));
+        quoted(out, key);
+        let _ = write!(out, " {from} -> {to} bytes");
+      },
+    );
+    section(
+      &mut out,
+      "bytes without a glyph in the encoding",
+      &self.missing_glyphs,
+      |out, (at, key, bytes)| {
+        let _ = write!(out, "{}: ", self.location(*at));
+        quoted(out, key);
+        for c in bytes {
+          let _ = write!(out, " 0x{c:02x}");
+        }
+      },
+    );
+    out
+  }
+
+  pub fn write(&self, report: &Path) -> Result<()> {
+    std::fs::write(report, self.render())?;
+    Ok(())
+  }
+}
+
+fn section<T>(out: &mut Vec<u8>, title: &str, items: &[T], line: impl Fn(&mut Vec<u8>, &T)) {
+  if items.is_empty() {
+    return;
+  }
+  let _ = writeln!(out, "\n{title}: {}", items.len());
+  for item in items {
+    out.extend_from_slice(b"  ");
+    line(out, item);
+    out.push(b'\n');
+  }
+}
+
+fn quoted(out: &mut Vec<u8>, value: &[u8]) {
+  out.push(b'"');
+  out.extend_from_slice(value);
+  out.push(b'"');
+}
+
+pub fn report_path() -> PathBuf {
+  Path::new(&CONFIG.settings.log_file).with_file_name(REPORT_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn collects_problems() {
+    let mut glyphs = [false; 256];
+    glyphs[32..127].fill(true);
+    glyphs[0xe0] = true;
+    let mut diagnostics = Diagnostics::new(2.0, Some(glyphs));
+    let layer = diagnostics.layer("test.csv");
+    let at = |line| Location { layer, line };
+
+    let rows: [(usize, &[u8], &str, &[u8]); 7] = [
+      (1, b"Stone", "", b"\xe0\xe0"),
+      (2, b"Stone", "", b"\xe0\xe0\xe0"),
+      (3, b"Bar", "", b"Bar"),
+      (4, b"ab", "", b"abcde"),
+      (5, b"Order", "", b"\x81\xe0\x81\x90"),
+      (6, b"Stone", "", b"\xe0\xe0\xe0"),
+      (7, b"Stone", "addst", b"\xe0"),
+    ];
+    for (line, key, context, value) in rows {
+      diagnostics.value(at(line), key, value);
+      diagnostics.entry(at(line), key, context, value);
+    }
+    diagnostics.empty(at(8), b"Ore");
+    diagnostics.malformed(at(9), String::from("quote inside unquoted field"));
+
+    assert_eq!(diagnostics.duplicates.len(), 1);
+    assert_eq!(diagnostics.duplicates[0].previous_at, at(1));
+    assert_eq!(diagnostics.duplicates[0].at, at(2));
+    assert_eq!(diagnostics.identical, vec![(at(3), b"Bar".to_vec())]);
+    assert_eq!(diagnostics.grown, vec![(at(4), b"ab".to_vec(), 2, 5)]);
+    assert_eq!(diagnostics.missing_glyphs, vec![(at(5), b"Order".to_vec(), vec![0x81, 0x90])]);
+    assert_eq!(diagnostics.problems(), 6);
+
+    let report = diagnostics.render();
+    let report = String::from_utf8_lossy(&report);
+    assert!(report.contains("test.csv:2: \"Stone\" = \"\u{fffd}\u{fffd}\u{fffd}\" overrides test.csv:1"));
+    assert!(report.contains("empty translations: 1\n  test.csv:8: \"Ore\"\n"));
+    assert!(report.contains("test.csv:5: \"Order\" 0x81 0x90\n"));
+  }
+
+  #[test]
+  fn overrides_from_later_layers_are_not_problems() {
+    let mut diagnostics = Diagnostics::new(3.0, None);
+    let base = diagnostics.layer("dictionary.csv");
+    let user = diagnostics.layer("dictionary.user.csv");
+
+    assert_eq!(diagnostics.entry(Location { layer: base, line: 1 }, b"Stone", "", b"a"), None);
+    assert_eq!(
+      diagnostics.entry(Location { layer: user, line: 4 }, b"Stone", "", b"b"),
+      Some(Location { layer: base, line: 1 })
+    );
+    assert_eq!(diagnostics.problems(), 0);
+    assert_eq!(diagnostics.location(Location { layer: user, line: 4 }), "dictionary.user.csv:4");
+  }
+}
@@ -1,70 +1,654 @@
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use std::collections::HashMap;
-use std::io::prelude::*;
+use std::sync::Arc;
 
-use crate::constants::PATH_DICTIONARY;
+use crate::compiled::{self, Compiled};
+use crate::csv;
+use crate::diagnostics::{self, Diagnostics, Location};
+use crate::paths::PATHS;
+use crate::template::{self, Templates};
 use crate::utils;
 
-#[static_init::dynamic]
-pub static DICTIONARY: Dictionary = Dictionary::new(PATH_DICTIONARY);
+// hooks that look strings up, usable in the optional third dictionary column
+pub const CONTEXTS: &[&str] = &[
+  "string_copy_n",
+  "string_append_n",
+  "std_string_ctor",
+  "std_string_append",
+  "std_string_assign",
+  "addst",
+  "addst_top",
+  "addst_flag",
+];
+
+#[static_init::dynamic(lazy)]
+pub static DICTIONARY: ArcSwap<Dictionary> = ArcSwap::from_pointee(Dictionary::new(layers()));
 
-#[allow(dead_code)]
 pub struct Dictionary {
-  map: HashMap<Vec<u8>, Vec<u8>>,
-  path: &'static str,
+  entries: Entries,
+  templates: Templates,
+  layers: Vec<String>,
 }
 
-impl Dictionary {
-  pub fn new(path: &'static str) -> Self {
+enum Entries {
+  Parsed(HashMap<Vec<u8>, Variants>),
+  Compiled(Compiled),
+}
+
+// translations of one key: the context-free one and those for specific hooks
+#[derive(Default)]
+pub struct Variants {
+  plain: Option<Translation>,
+  contextual: Vec<(String, Translation)>,
+}
+
+impl Variants {
+  fn insert(&mut self, context: &str, translation: Translation) {
+    if context.is_empty() {
+      self.plain = Some(translation);
+    } else if let Some(slot) = self.contextual.iter_mut().find(|(c, _)| c == context) {
+      slot.1 = translation;
+    } else {
+      self.contextual.push((String::from(context), translation));
+    }
+  }
+
+  fn get(&self, context: &str) -> Option<&Translation> {
+    if context.is_empty() {
+      return self.plain.as_ref();
+    }
+    self.contextual.iter().find(|(c, _)| c == context).map(|(_, t)| t)
+  }
+
+  fn len(&self) -> usize {
+    self.plain.iter().count() + self.contextual.len()
+  }
+
+  fn iter(&self) -> impl Iterator<Item = (&str, &Translation)> {
+    self
+      .plain
+      .iter()
+      .map(|t| ("", t))
+      .chain(self.contextual.iter().map(|(c, t)| (c.as_str(), t)))
+  }
+}
+
+// NUL-terminated and never shorter than a std::string SSO buffer, so hooks can pass it to DF by pointer
+// for as long as they hold the dictionary guard, without any per-call allocation
+pub struct Translation {
+  buf: Box<[u8]>,
+  len: usize,
+}
+
+impl Translation {
+  pub const MIN_CAPACITY: usize = 16;
+
+  pub fn new(value: &[u8]) -> Self {
+    let mut buf = value.to_vec();
+    buf.resize(std::cmp::max(value.len() + 1, Self::MIN_CAPACITY), 0);
     Self {
-      map: match Dictionary::load(path) {
-        Ok(value) => value,
-        Err(_) => {
-          log::error!("unable to load dictionary {path}");
+      buf: buf.into_boxed_slice(),
+      len: value.len(),
+    }
+  }
+
+  pub fn as_ptr(&self) -> *const u8 {
+    self.buf.as_ptr()
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.buf[..self.len]
+  }
+}
+
+impl PartialEq for Translation {
+  fn eq(&self, other: &Self) -> bool {
+    self.as_bytes() == other.as_bytes()
+  }
+}
+
+pub enum Found<'a> {
+  Exact(&'a Translation),
+  // value inside the compiled dictionary mapping, NUL-terminated and padded the same way as Translation
+  Mapped(&'a [u8]),
+  // built for this string instead of stored in the dictionary, by a template or by a later stage
+  Owned(Arc<Translation>),
+}
+
+impl Found<'_> {
+  pub fn as_ptr(&self) -> *const u8 {
+    match self {
+      Found::Exact(translation) => translation.as_ptr(),
+      Found::Mapped(value) => value.as_ptr(),
+      Found::Owned(translation) => translation.as_ptr(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    match self {
+      Found::Exact(translation) => translation.len(),
+      Found::Mapped(value) => value.len(),
+      Found::Owned(translation) => translation.len(),
+    }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    match self {
+      Found::Exact(translation) => translation.as_bytes(),
+      Found::Mapped(value) => value,
+      Found::Owned(translation) => translation.as_bytes(),
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct DictionaryDiff {
+  pub added: usize,
+  pub removed: usize,
+  pub changed: usize,
+}
+
+impl Dictionary {
+  pub fn new(layers: Vec<String>) -> Self {
+    match Dictionary::open(layers.clone()) {
+      Ok(dictionary) => dictionary,
+      Err(_) => {
+        log::error!("unable to load dictionary {}", PATHS.dictionary);
+        // a missing dictionary is already among the config problems
+        if std::path::Path::new(&PATHS.dictionary).exists() {
           utils::message_box(
             "dfint hook error",
-            format!("Unable to load dictionary {path}").as_str(),
+            format!("Unable to load dictionary {}", PATHS.dictionary).as_str(),
             utils::MessageIconType::Warning,
           );
-          HashMap::<Vec<u8>, Vec<u8>>::new()
         }
-      },
-      path,
+        Self {
+          entries: Entries::Parsed(HashMap::new()),
+          templates: Templates::default(),
+          layers,
+        }
+      }
     }
   }
 
-  pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
-    self.map.get(key)
+  // uses the compiled dictionary when it was built from the current layers, otherwise parses the csv
+  // and rebuilds it for the next start
+  fn open(layers: Vec<String>) -> Result<Self> {
+    let checksum = compiled::checksum(&layers)?;
+    let path = &compiled::versioned(&PATHS.dictionary_compiled, checksum);
+    match Compiled::open(path, checksum) {
+      Ok(compiled) => {
+        let (templates, _) = Templates::new(compiled.templates().map(|(k, v)| ((), k.to_vec(), v.to_vec())).collect());
+        log::info!("dictionary loaded from {path}");
+        // what loading the csv would have logged and reported
+        for note in compiled.notes() {
+          log::info!("{note}");
+        }
+        let report = diagnostics::report_path();
+        if let Err(err) = std::fs::write(&report, compiled.report()) {
+          log::error!("unable to write dictionary report {}, {err:?}", report.display());
+        }
+        return Ok(Self {
+          entries: Entries::Compiled(compiled),
+          templates,
+          layers,
+        });
+      }
+      Err(err) => log::info!("compiled dictionary {path} not used, {err}"),
+    }
+
+    let mut diagnostics = Diagnostics::from_config();
+    let (map, templates) = Dictionary::load(&layers, &mut diagnostics)?;
+    write_report(&diagnostics);
+    let entries = map
+      .iter()
+      .flat_map(|(k, variants)| variants.iter().map(move |(c, v)| (k.as_slice(), c, v.as_bytes())));
+    let notes = compiled::Notes {
+      lines: diagnostics.notes(),
+      report: &diagnostics.render(),
+    };
+    match compiled::build(path, checksum, entries, templates.sources(), notes) {
+      Ok(_) => {
+        log::info!("compiled dictionary written to {path}");
+        compiled::remove_stale(&PATHS.dictionary_compiled, path);
+      }
+      Err(err) => log::warn!("unable to write compiled dictionary {path}, {err:?}"),
+    }
+    Ok(Self {
+      entries: Entries::Parsed(map),
+      templates,
+      layers,
+    })
+  }
+
+  // the entry for exactly this context, an empty context is the context-free entry
+  fn get_exact(&self, key: &[u8], context: &str) -> Option<Found<'_>> {
+    match &self.entries {
+      Entries::Parsed(map) => map.get(key).and_then(|variants| variants.get(context)).map(Found::Exact),
+      Entries::Compiled(compiled) => compiled.get(key, context).map(Found::Mapped),
+    }
+  }
+
+  // the entry for the hook the string came through, falling back to the context-free one
+  pub fn get(&self, key: &[u8], context: &str) -> Option<Found<'_>> {
+    if context.is_empty() {
+      return self.get_exact(key, "");
+    }
+    self.get_exact(key, context).or_else(|| self.get_exact(key, ""))
+  }
+
+  pub fn template(&self, key: &[u8]) -> Option<Found<'_>> {
+    self.templates.lookup(key).map(Found::Owned)
   }
 
   pub fn size(&self) -> usize {
-    self.map.len()
+    match &self.entries {
+      Entries::Parsed(map) => map.values().map(Variants::len).sum(),
+      Entries::Compiled(compiled) => compiled.len(),
+    }
+  }
+
+  fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &str, &[u8])> + '_> {
+    match &self.entries {
+      Entries::Parsed(map) => Box::new(
+        map
+          .iter()
+          .flat_map(|(k, variants)| variants.iter().map(move |(c, v)| (k.as_slice(), c, v.as_bytes()))),
+      ),
+      Entries::Compiled(compiled) => Box::new(compiled.entries()),
+    }
+  }
+
+  pub fn templates(&self) -> usize {
+    self.templates.len()
+  }
+
+  pub fn layers(&self) -> &[String] {
+    &self.layers
+  }
+
+  pub fn diff(&self, other: &Dictionary) -> DictionaryDiff {
+    let mut diff = DictionaryDiff::default();
+    for (k, c, v) in other.iter() {
+      match self.get_exact(k, c) {
+        Some(old) if old.as_bytes() != v => diff.changed += 1,
+        Some(_) => (),
+        None => diff.added += 1,
+      }
+    }
+    diff.removed = self.iter().filter(|(k, c, _)| other.get_exact(k, c).is_none()).count();
+    let old: HashMap<&[u8], &[u8]> = self.templates.sources().iter().map(|(k, v)| (&k[..], &v[..])).collect();
+    for (k, v) in other.templates.sources() {
+      match old.get(&k[..]) {
+        Some(old) if old != v => diff.changed += 1,
+        Some(_) => (),
+        None => diff.added += 1,
+      }
+    }
+    diff.removed += old.len() - other.templates.sources().iter().filter(|(k, _)| old.contains_key(&k[..])).count();
+    diff
+  }
+
+  // the first layer is required, the rest are optional; later layers override earlier ones
+  fn load(layers: &[String], diagnostics: &mut Diagnostics) -> Result<(HashMap<Vec<u8>, Variants>, Templates)> {
+    let mut map = HashMap::<Vec<u8>, Variants>::new();
+    let mut templates = HashMap::<Vec<u8>, (Location, Vec<u8>)>::new();
+    let mut malformed = 0;
+    for (i, path) in layers.iter().enumerate() {
+      let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if i == 0 => return Err(err.into()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+        Err(err) => {
+          log::warn!("unable to read dictionary layer {path}, {err}");
+          continue;
+        }
+      };
+      let layer = diagnostics.layer(path);
+      let mut items = 0;
+      let mut overrides = 0;
+      for record in csv::Reader::new(&contents) {
+        match record {
+          Ok(record) if record.fields.len() == 2 || record.fields.len() == 3 => {
+            let (k, v) = (&record.fields[0], &record.fields[1]);
+            let at = Location {
+              layer,
+              line: record.line,
+            };
+            let contexts = match record.fields.get(2).map_or(Ok(vec![String::new()]), |c| contexts(c)) {
+              Ok(contexts) => contexts,
+              Err(message) => {
+                malformed += 1;
+                log::warn!("{path}: line {}: {message}", record.line);
+                diagnostics.malformed(at, message);
+                continue;
+              }
+            };
+            // rows with an empty side were never loaded, keep it that way
+            if k.is_empty() {
+              malformed += 1;
+              log::warn!("{path}: line {}: empty key", record.line);
+              diagnostics.malformed(at, String::from("empty key"));
+              continue;
+            }
+            if v.is_empty() {
+              diagnostics.empty(at, k);
+              continue;
+            }
+            let template = template::is_template(k);
+            if template && contexts.iter().any(|c| !c.is_empty()) {
+              malformed += 1;
+              log::warn!("{path}: line {}: templates cannot have a context", record.line);
+              diagnostics.malformed(at, String::from("templates cannot have a context"));
+              continue;
+            }
+            diagnostics.value(at, k, v);
+            for context in contexts {
+              items += 1;
+              if let Some(previous) = diagnostics.entry(at, k, &context, v) {
+                overrides += 1;
+                let line = format!(
+                  "{}: \"{}\" overrides {}",
+                  diagnostics.location(at),
+                  String::from_utf8_lossy(k),
+                  diagnostics.location(previous)
+                );
+                diagnostics.note(line);
+              }
+              if template {
+                templates.insert(k.clone(), (at, v.clone()));
+                continue;
+              }
+              map.entry(k.clone()).or_default().insert(&context, Translation::new(v));
+            }
+          }
+          Ok(record) => {
+            malformed += 1;
+            let message = format!("expected 2 or 3 fields, found {}", record.fields.len());
+            log::warn!("{path}: line {}: {message}", record.line);
+            diagnostics.malformed(
+              Location {
+                layer,
+                line: record.line,
+              },
+              message,
+            );
+          }
+          Err(err) => {
+            malformed += 1;
+            log::warn!("{path}: {err}");
+            diagnostics.malformed(Location { layer, line: err.line }, err.message);
+          }
+        }
+      }
+      if i > 0 {
+        diagnostics.note(format!("dictionary layer {path}, items {items}, overrides {overrides}"));
+      }
+    }
+
+    let mut templates: Vec<(Location, Vec<u8>, Vec<u8>)> =
+      templates.into_iter().map(|(k, (at, v))| (at, k, v)).collect();
+    templates.sort_by_key(|(at, _, _)| (at.layer, at.line));
+    let (templates, errors) = Templates::new(templates);
+    for (at, message) in errors {
+      malformed += 1;
+      log::warn!("{}: {message}", diagnostics.location(at));
+      diagnostics.malformed(at, message);
+    }
+    if malformed > 0 {
+      log::warn!("dictionary: {malformed} malformed rows skipped");
+    }
+    Ok((map, templates))
+  }
+}
+
+// the optional third column: hook names separated by |, empty for the context-free entry
+fn contexts(field: &[u8]) -> Result<Vec<String>, String> {
+  let field = std::str::from_utf8(field).map_err(|_| String::from("context is not valid utf-8"))?;
+  if field.trim().is_empty() {
+    return Ok(vec![String::new()]);
   }
+  let mut contexts = Vec::new();
+  for context in field.split('|').map(str::trim) {
+    if !CONTEXTS.contains(&context) {
+      return Err(format!("unknown context \"{context}\", expected one of {}", CONTEXTS.join(", ")));
+    }
+    if !contexts.iter().any(|c| c == context) {
+      contexts.push(String::from(context));
+    }
+  }
+  Ok(contexts)
+}
 
-  pub fn _data(&self) -> &HashMap<Vec<u8>, Vec<u8>> {
-    &self.map
+// base dictionary first, then every dictionary.d/*.csv in lexical order, then the user's own overrides
+pub fn layers() -> Vec<String> {
+  let mut layers = vec![PATHS.dictionary.clone()];
+  if let Ok(entries) = std::fs::read_dir(&PATHS.dictionary_layers) {
+    let mut files: Vec<String> = entries
+      .flatten()
+      .map(|entry| entry.path())
+      .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "csv"))
+      .filter_map(|path| path.to_str().map(String::from))
+      .collect();
+    files.sort();
+    layers.extend(files);
   }
+  layers.push(PATHS.dictionary_user.clone());
+  layers
+}
 
-  pub fn _reload(&mut self) -> Result<()> {
-    self.map = Self::load(self.path)?;
-    Ok(())
+fn write_report(diagnostics: &Diagnostics) {
+  let report = diagnostics::report_path();
+  match diagnostics.write(&report) {
+    Ok(_) if diagnostics.problems() > 0 => {
+      log::warn!("dictionary: {}, see {}", diagnostics.summary(), report.display())
+    }
+    Ok(_) => (),
+    Err(err) => log::error!("unable to write dictionary report {}, {err:?}", report.display()),
   }
+}
 
-  #[allow(unused_must_use)]
-  fn load(path: &str) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
-    let mut file = std::fs::File::open(path)?;
-    let mut contents: Vec<u8> = Vec::new();
-    file.read_to_end(&mut contents);
-    let mut map = HashMap::<Vec<u8>, Vec<u8>>::new();
-    const QUOTE: &u8 = &b"\""[0];
-    for item in regex::bytes::Regex::new(r#"(?-u)"(.+)","(.+)""#)?.captures_iter(&contents) {
-      let mut k = item[1].to_vec();
-      let mut v = item[2].to_vec();
-      v.push(0);
-      k.dedup_by(|a, b| a == QUOTE && b == QUOTE);
-      v.dedup_by(|a, b| a == QUOTE && b == QUOTE);
-      map.insert(k, v);
+// hooks keep the old dictionary alive through their guard, so swapping is safe mid-frame
+pub fn reload() -> Result<DictionaryDiff> {
+  let current = DICTIONARY.load();
+  let layers = layers();
+  let fresh = Dictionary::open(layers)?;
+  let diff = current.diff(&fresh);
+  DICTIONARY.store(Arc::new(fresh));
+  Ok(diff)
+}
+
+#[cfg(test)]
+impl Dictionary {
+  pub fn from_entries(entries: &[(&[u8], &str, &[u8])], templates: &[(&[u8], &[u8])]) -> Self {
+    let mut map = HashMap::<Vec<u8>, Variants>::new();
+    for (k, c, v) in entries {
+      map.entry(k.to_vec()).or_default().insert(c, Translation::new(v));
     }
-    Ok(map)
+    let (templates, _) = Templates::new(templates.iter().map(|(k, v)| ((), k.to_vec(), v.to_vec())).collect());
+    Dictionary {
+      entries: Entries::Parsed(map),
+      templates,
+      layers: vec![String::from("test")],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::alloc::{GlobalAlloc, Layout, System};
+  use std::cell::Cell;
+
+  struct CountingAlloc;
+
+  thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+  }
+
+  unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+      let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+      System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+      System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+      let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+      System.realloc(ptr, layout, new_size)
+    }
+  }
+
+  #[global_allocator]
+  static GLOBAL: CountingAlloc = CountingAlloc;
+
+  fn dictionary(entries: &[(&[u8], &str, &[u8])]) -> Dictionary {
+    Dictionary::from_entries(entries, &[])
+  }
+
+  #[test]
+  fn translation_is_nul_terminated_and_padded() {
+    let short = Translation::new(b"abc");
+    assert_eq!(short.len(), 3);
+    assert_eq!(short.as_bytes(), b"abc");
+    let raw = unsafe { std::slice::from_raw_parts(short.as_ptr(), 16) };
+    assert_eq!(&raw[3..], &[0u8; 13]);
+
+    let long = Translation::new(&[b'x'; 40]);
+    assert_eq!(long.len(), 40);
+    assert_eq!(unsafe { *long.as_ptr().add(40) }, 0);
+  }
+
+  #[test]
+  fn lookup_does_not_allocate() {
+    let handle = ArcSwap::from_pointee(dictionary(&[
+      (b"Stone", "", b"\xca\xe0\xec\xe5\xed\xfc"),
+      (b"Stone", "addst", b"ui"),
+      (b"A very long string that does not fit into SSO", "", b"translated"),
+    ]));
+    let keys: [&[u8]; 3] = [b"Stone", b"A very long string that does not fit into SSO", b"missing"];
+
+    // the first load registers this thread with arc-swap, which allocates once
+    drop(handle.load());
+
+    let before = ALLOCATIONS.with(|c| c.get());
+    let mut sum = 0usize;
+    for i in 0..3_000_000 {
+      if let Some(translate) = handle.load().get(keys[i % keys.len()], CONTEXTS[i % CONTEXTS.len()]) {
+        sum += unsafe { *translate.as_ptr() } as usize + translate.len();
+      }
+    }
+    let after = ALLOCATIONS.with(|c| c.get());
+
+    assert!(sum > 0);
+    assert_eq!(after - before, 0);
+  }
+
+  #[test]
+  fn load_keeps_well_formed_rows() {
+    let path = std::env::temp_dir().join("dfint-dictionary-load.csv");
+    std::fs::write(
+      &path,
+      b"\"Stone\",\"\xca\xe0\xec\xe5\xed\xfc\"\n\"a \"\"b\"\", c\",\"d\"\r\n\"two\nlines\",\"x\"\nbroken\"row,\"y\"\n\"one\"\n\"\",\"empty\"\n",
+    )
+    .unwrap();
+    let mut diagnostics = Diagnostics::new(3.0, None);
+    let (map, _) = Dictionary::load(&[String::from(path.to_str().unwrap())], &mut diagnostics).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let get = |k: &[u8]| map[k].get("").unwrap().as_bytes();
+    assert_eq!(map.len(), 3);
+    assert_eq!(get(b"Stone"), b"\xca\xe0\xec\xe5\xed\xfc");
+    assert_eq!(get(b"a \"b\", c"), b"d");
+    assert_eq!(get(b"two\nlines"), b"x");
+    assert_eq!(diagnostics.problems(), 3);
+  }
+
+  #[test]
+  fn later_layers_override_earlier_ones() {
+    let dir = std::env::temp_dir().join("dfint-dictionary-layers");
+    std::fs::create_dir_all(&dir).unwrap();
+    let layer = |name: &str, contents: &[u8]| {
+      let path = dir.join(name);
+      std::fs::write(&path, contents).unwrap();
+      String::from(path.to_str().unwrap())
+    };
+    let layers = vec![
+      layer("base.csv", b"\"Stone\",\"base\"\n\"Bar\",\"base\"\n\"{number:1} days\",\"base {1}\"\n"),
+      layer("mod.csv", b"\"Stone\",\"mod\"\n\"{number:1} days\",\"mod {1}\"\n"),
+      String::from(dir.join("missing.csv").to_str().unwrap()),
+      layer("user.csv", b"\"Stone\",\"user\"\n"),
+    ];
+    let mut diagnostics = Diagnostics::new(3.0, None);
+    let (map, templates) = Dictionary::load(&layers, &mut diagnostics).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(map[b"Stone".as_slice()].get("").unwrap().as_bytes(), b"user");
+    assert_eq!(map[b"Bar".as_slice()].get("").unwrap().as_bytes(), b"base");
+    assert_eq!(templates.lookup(b"3 days").unwrap().as_bytes(), b"mod 3");
+    assert_eq!(diagnostics.problems(), 0);
+    let notes = diagnostics.notes();
+    assert_eq!(notes.len(), 5);
+    assert_eq!(notes[0], format!("{}:1: \"Stone\" overrides {}:1", layers[1], layers[0]));
+    assert_eq!(notes[2], format!("dictionary layer {}, items 2, overrides 2", layers[1]));
+    assert_eq!(notes[3], format!("{}:1: \"Stone\" overrides {}:1", layers[3], layers[1]));
+
+    let mut diagnostics = Diagnostics::new(3.0, None);
+    assert!(Dictionary::load(&layers[2..], &mut diagnostics).is_err());
+  }
+
+  #[test]
+  fn context_entries_fall_back_to_plain_ones() {
+    let path = std::env::temp_dir().join("dfint-dictionary-contexts.csv");
+    std::fs::write(
+      &path,
+      b"\"Stone\",\"rock\"\n\"Stone\",\"label\",\"addst|addst_top\"\n\"Bar\",\"pub\",\"std_string_ctor\"\n\"Order\",\"x\",\"nowhere\"\n\"{number:1} days\",\"{1}\",\"addst\"\n\"Ore\",\"Ore\",\"addst|addst_top\"\n",
+    )
+    .unwrap();
+    let mut diagnostics = Diagnostics::new(3.0, None);
+    let (map, templates) = Dictionary::load(&[String::from(path.to_str().unwrap())], &mut diagnostics).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let d = Dictionary {
+      entries: Entries::Parsed(map),
+      templates,
+      layers: vec![String::from("test")],
+    };
+    let get = |k: &[u8], c: &str| d.get(k, c).map(|f| f.as_bytes().to_vec());
+
+    assert_eq!(get(b"Stone", "addst"), Some(b"label".to_vec()));
+    assert_eq!(get(b"Stone", "addst_top"), Some(b"label".to_vec()));
+    assert_eq!(get(b"Stone", "std_string_ctor"), Some(b"rock".to_vec()));
+    assert_eq!(get(b"Stone", ""), Some(b"rock".to_vec()));
+    assert_eq!(get(b"Bar", "std_string_ctor"), Some(b"pub".to_vec()));
+    assert_eq!(get(b"Bar", "addst"), None);
+    assert_eq!(get(b"Order", "addst"), None);
+    assert_eq!(get(b"3 days", "addst"), None);
+    assert_eq!(d.size(), 6);
+    // the identical Ore row is reported once, not once per context
+    assert_eq!(diagnostics.problems(), 3);
+  }
+
+  #[test]
+  fn diff_counts_changes() {
+    let old = dictionary(&[(b"a", "", b"1"), (b"b", "", b"2"), (b"c", "", b"3"), (b"a", "addst", b"5")]);
+    let new = dictionary(&[(b"a", "", b"1"), (b"b", "", b"20"), (b"d", "", b"4"), (b"a", "addst", b"6")]);
+    let diff = old.diff(&new);
+    assert_eq!((diff.added, diff.removed, diff.changed), (1, 1, 2));
+  }
+
+  #[test]
+  fn diff_counts_template_changes() {
+    let old = Dictionary::from_entries(&[], &[(b"{number:1} days", b"1"), (b"{word:1} arrived", b"2")]);
+    let new = Dictionary::from_entries(&[], &[(b"{number:1} days", b"10"), (b"{word:1} left", b"3")]);
+    let diff = old.diff(&new);
+    assert_eq!((diff.added, diff.removed, diff.changed), (1, 1, 1));
   }
 }
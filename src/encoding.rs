@@ -2,7 +2,7 @@ use anyhow::{anyhow, Result};
 use std::{collections::HashMap, path::Path};
 use toml::{map::Map, Table, Value};
 
-use crate::constants::PATH_ENCODING;
+use crate::paths::PATHS;
 
 pub struct Encoding {
   pub capitalize: Vec<u8>,
@@ -16,10 +16,7 @@ pub struct Encoding {
 
 impl Encoding {
   pub fn new() -> Self {
-    match Self::parse_encodings(Path::new(PATH_ENCODING)) {
-      Ok(v) => v,
-      Err(_) => Self::default(),
-    }
+    Self::parse_encodings(Path::new(&PATHS.encoding)).unwrap_or_default()
   }
 
   fn parse_encodings(path: &Path) -> Result<Encoding> {
@@ -47,6 +44,19 @@ impl Encoding {
     })
   }
 
+  // printable ascii plus every byte the encoding maps a unicode symbol to
+  pub fn glyphs(&self) -> Option<[bool; 256]> {
+    if !self.parsed {
+      return None;
+    }
+    let mut glyphs = [false; 256];
+    glyphs[32..127].fill(true);
+    for v in self.utf.values() {
+      glyphs[*v as usize] = true;
+    }
+    Some(glyphs)
+  }
+
   fn utf_transition(map: &Map<String, Value>) -> Result<HashMap<u32, u8>> {
     let mut out: HashMap<u32, u8> = HashMap::new();
     for (k, v) in map {
@@ -62,7 +72,7 @@ impl Encoding {
     };
     for (k, v) in map {
       for i in Self::str_to_array(k)? {
-        out[i as usize] = out[i as usize] + v.as_integer().ok_or(anyhow!("parsing err"))? as u8;
+        out[i as usize] += v.as_integer().ok_or(anyhow!("parsing err"))? as u8;
       }
     }
     Ok(out)
@@ -81,7 +91,7 @@ impl Encoding {
     Ok(out)
   }
 
-  fn str_to_array(value: &String) -> Result<Vec<u8>> {
+  fn str_to_array(value: &str) -> Result<Vec<u8>> {
     if value.contains(":") {
       let r: Vec<&str> = value.split(":").collect();
       let start = r[0].parse::<u8>()?;
@@ -3,98 +3,58 @@ use retour::static_detour;
 use std::ffi::c_char;
 
 use crate::config::CONFIG;
-use crate::cxxstring::CxxString;
-use crate::dictionary::DICTIONARY;
+use crate::cxxstring::{CxxStrMut, CxxStrRef, LAYOUT};
+use crate::registry::{self, Group, Hook, Method};
+use crate::translator::TRANSLATOR;
+#[cfg(target_os = "linux")]
+use crate::symbols;
+#[cfg(target_os = "windows")]
 use crate::utils;
 
 use r#macro::hook;
 
 #[cfg(target_os = "linux")]
-#[static_init::dynamic]
-static ENABLER: usize = unsafe {
-  match CONFIG.symbol.is_some() {
-    true => {
-      utils::symbol_handle_self::<*const i64>(&CONFIG.symbol.as_ref().unwrap().enabler.as_ref().unwrap()[1]) as usize
-    }
-    false => 0 as usize,
-  }
-};
+#[static_init::dynamic(lazy)]
+static ENABLER: usize =
+  unsafe { symbols::resolve::<usize>("enabler", CONFIG.symbol.as_ref().and_then(|s| s.enabler.as_ref())).unwrap_or(0) };
+
+// hooks switched on by their group in [settings] and by their own entry in [hooks]
+fn active(hook: &Hook) -> bool {
+  let group = match hook.group {
+    Group::Translation => CONFIG.settings.enable_translation,
+    Group::Search => CONFIG.settings.enable_search && CONFIG.encoding.parsed,
+  };
+  group && CONFIG.hooks.enabled(hook.name)
+}
 
 pub unsafe fn attach_all() -> Result<()> {
-  if CONFIG.settings.enable_translation {
-    attach_string_copy_n()?;
-    attach_string_append_n()?;
-    attach_std_string_ctor()?;
-    attach_std_string_append()?;
-    attach_std_string_assign()?;
-    attach_addst()?;
-    attach_addst_top()?;
-    attach_addst_flag()?;
+  let (active, inactive): (Vec<&Hook>, Vec<&Hook>) =
+    registry::sorted().into_iter().filter(|hook| hook.method != Method::Bypass).partition(|hook| active(hook));
+  log::info!("hooks enabled: {}", active.iter().map(|hook| hook.name).collect::<Vec<_>>().join(", "));
+  let disabled: Vec<&str> = inactive.iter().map(|hook| hook.name).filter(|name| !CONFIG.hooks.enabled(name)).collect();
+  if !disabled.is_empty() {
+    log::info!("hooks disabled in config: {}", disabled.join(", "));
   }
-  if CONFIG.settings.enable_search && CONFIG.encoding.parsed {
-    attach_standardstringentry()?;
-    attach_simplify_string()?;
-    attach_upper_case_string()?;
-    attach_lower_case_string()?;
-    attach_capitalize_string_words()?;
-    attach_capitalize_string_first_word()?;
+  for hook in active {
+    hook.attach()?;
+  }
+  for (name, group, method, state, _) in registry::status() {
+    log::debug!("hook {name}: {group:?} by {method:?}, {state}");
   }
-  Ok(())
-}
-
-pub unsafe fn enable_translation() -> Result<()> {
-  enable_string_copy_n()?;
-  enable_string_append_n()?;
-  enable_std_string_ctor()?;
-  enable_std_string_append()?;
-  enable_std_string_assign()?;
-  enable_addst()?;
-  enable_addst_top()?;
-  enable_addst_flag()?;
-  Ok(())
-}
-
-pub unsafe fn enable_search() -> Result<()> {
-  enable_standardstringentry()?;
-  enable_simplify_string()?;
-  enable_upper_case_string()?;
-  enable_lower_case_string()?;
-  enable_capitalize_string_words()?;
-  enable_capitalize_string_first_word()?;
   Ok(())
 }
 
 pub unsafe fn enable_all() -> Result<()> {
-  enable_translation()?;
-  enable_search()?;
-  Ok(())
-}
-
-pub unsafe fn disable_translation() -> Result<()> {
-  disable_string_copy_n()?;
-  disable_string_append_n()?;
-  disable_std_string_ctor()?;
-  disable_std_string_append()?;
-  disable_std_string_assign()?;
-  disable_addst()?;
-  disable_addst_top()?;
-  disable_addst_flag()?;
-  Ok(())
-}
-
-pub unsafe fn disable_search() -> Result<()> {
-  disable_standardstringentry()?;
-  disable_simplify_string()?;
-  disable_upper_case_string()?;
-  disable_lower_case_string()?;
-  disable_capitalize_string_words()?;
-  disable_capitalize_string_first_word()?;
+  for hook in registry::HOOKS.iter() {
+    hook.enable()?;
+  }
   Ok(())
 }
 
 pub unsafe fn disable_all() -> Result<()> {
-  disable_translation()?;
-  disable_search()?;
+  for hook in registry::HOOKS.iter() {
+    hook.disable()?;
+  }
   Ok(())
 }
 
@@ -102,16 +62,13 @@ pub unsafe fn disable_all() -> Result<()> {
 #[cfg_attr(target_os = "linux", hook(bypass))]
 fn string_copy_n(dst: *mut c_char, src: *const u8, size: usize) -> *mut c_char {
   unsafe {
-    match (std::slice::from_raw_parts(src, size), size > 1) {
-      (value, true) => match DICTIONARY.get(value) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          original!(dst, ptr, len - 1)
-        }
-        _ => original!(dst, src, size),
-      },
-      (_, _) => original!(dst, src, size),
+    if size <= 1 {
+      return original!(dst, src, size);
     }
+    TRANSLATOR.apply(std::slice::from_raw_parts(src, size), "string_copy_n", |found| match found {
+      Some(translate) => original!(dst, translate.as_ptr(), translate.len()),
+      None => original!(dst, src, size),
+    })
   }
 }
 
@@ -119,16 +76,13 @@ fn string_copy_n(dst: *mut c_char, src: *const u8, size: usize) -> *mut c_char {
 #[cfg_attr(target_os = "linux", hook(bypass))]
 fn string_append_n(dst: *mut c_char, src: *const u8, size: usize) -> *mut c_char {
   unsafe {
-    match (std::slice::from_raw_parts(src, size), size > 1) {
-      (value, true) => match DICTIONARY.get(value) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          original!(dst, ptr, len - 1)
-        }
-        _ => original!(dst, src, size),
-      },
-      (_, _) => original!(dst, src, size),
+    if size <= 1 {
+      return original!(dst, src, size);
     }
+    TRANSLATOR.apply(std::slice::from_raw_parts(src, size), "string_append_n", |found| match found {
+      Some(translate) => original!(dst, translate.as_ptr(), translate.len()),
+      None => original!(dst, src, size),
+    })
   }
 }
 
@@ -136,16 +90,13 @@ fn string_append_n(dst: *mut c_char, src: *const u8, size: usize) -> *mut c_char
 #[cfg_attr(target_os = "linux", hook(bypass))]
 fn std_string_ctor(dst: *const u8, src: *const u8, size: usize) -> *const u8 {
   unsafe {
-    match (std::slice::from_raw_parts(src, size), size > 1) {
-      (value, true) => match DICTIONARY.get(value) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          original!(dst, ptr, len - 1)
-        }
-        _ => original!(dst, src, size),
-      },
-      (_, _) => original!(dst, src, size),
+    if size <= 1 {
+      return original!(dst, src, size);
     }
+    TRANSLATOR.apply(std::slice::from_raw_parts(src, size), "std_string_ctor", |found| match found {
+      Some(translate) => original!(dst, translate.as_ptr(), translate.len()),
+      None => original!(dst, src, size),
+    })
   }
 }
 
@@ -153,16 +104,11 @@ fn std_string_ctor(dst: *const u8, src: *const u8, size: usize) -> *const u8 {
 #[cfg_attr(target_os = "linux", hook(by_symbol))]
 fn std_string_append(dst: *const u8, src: *const u8) -> *const u8 {
   unsafe {
-    match std::ffi::CStr::from_ptr(src as *const c_char).to_bytes() {
-      (value) => match DICTIONARY.get(value) {
-        Some(translate) => {
-          let (ptr, _, _) = translate.to_owned().into_raw_parts();
-          original!(dst, ptr)
-        }
-        _ => original!(dst, src),
-      },
-      _ => original!(dst, src),
-    }
+    let value = std::ffi::CStr::from_ptr(src as *const c_char).to_bytes();
+    TRANSLATOR.apply(value, "std_string_append", |found| match found {
+      Some(translate) => original!(dst, translate.as_ptr()),
+      None => original!(dst, src),
+    })
   }
 }
 
@@ -170,92 +116,41 @@ fn std_string_append(dst: *const u8, src: *const u8) -> *const u8 {
 #[cfg_attr(target_os = "linux", hook(by_symbol))]
 fn std_string_assign(dst: *const u8, src: *const u8) -> *const u8 {
   unsafe {
-    match std::ffi::CStr::from_ptr(src as *const c_char).to_bytes() {
-      (value) => match DICTIONARY.get(value) {
-        Some(translate) => {
-          let (ptr, _, _) = translate.to_owned().into_raw_parts();
-          original!(dst, ptr)
-        }
-        _ => original!(dst, src),
-      },
-      _ => original!(dst, src),
-    }
+    let value = std::ffi::CStr::from_ptr(src as *const c_char).to_bytes();
+    TRANSLATOR.apply(value, "std_string_assign", |found| match found {
+      Some(translate) => original!(dst, translate.as_ptr()),
+      None => original!(dst, src),
+    })
   }
 }
 
+// addst and its variants take the text as a std::string, a translation is passed on as a std::string
+// that borrows the translation's buffer
+unsafe fn translate_cxx<R>(src: *const u8, context: &str, original: impl FnOnce(*const u8) -> R) -> R {
+  CxxStrRef::with(src, |s| {
+    TRANSLATOR.apply(s.as_bytes(), context, |found| match found {
+      Some(translate) => LAYOUT.with_borrowed(translate.as_ptr(), translate.len(), original),
+      None => original(src),
+    })
+  })
+}
+
 #[cfg_attr(target_os = "windows", hook(by_offset))]
 #[cfg_attr(target_os = "linux", hook(by_symbol))]
 fn addst(gps: usize, src: *const u8, justify: u8, space: u32) {
-  unsafe {
-    let s = CxxString::from_ptr(src);
-    match s.to_bytes_without_nul() {
-      converted => match DICTIONARY.get(converted) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          let mut cxxstr = CxxString::new(ptr, len - 1);
-          #[cfg(target_os = "linux")]
-          {
-            if cxxstr.len < 16 {
-              cxxstr.ptr = cxxstr.sso.buf.as_mut_ptr();
-            }
-          }
-          original!(gps, cxxstr.as_ptr(), justify, space)
-        }
-        _ => original!(gps, src, justify, space),
-      },
-      _ => original!(gps, src, justify, space),
-    }
-  }
+  unsafe { translate_cxx(src, "addst", |s| original!(gps, s, justify, space)) }
 }
 
 #[cfg_attr(target_os = "windows", hook(by_offset))]
 #[cfg_attr(target_os = "linux", hook(by_symbol))]
 fn addst_top(gps: usize, src: *const u8, justify: u8, space: u32) {
-  unsafe {
-    let s = CxxString::from_ptr(src);
-    match s.to_bytes_without_nul() {
-      converted => match DICTIONARY.get(converted) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          let mut cxxstr = CxxString::new(ptr, len - 1);
-          #[cfg(target_os = "linux")]
-          {
-            if cxxstr.len < 16 {
-              cxxstr.ptr = cxxstr.sso.buf.as_mut_ptr();
-            }
-          }
-          original!(gps, cxxstr.as_ptr(), justify, space)
-        }
-        _ => original!(gps, src, justify, space),
-      },
-      _ => original!(gps, src, justify, space),
-    }
-  }
+  unsafe { translate_cxx(src, "addst_top", |s| original!(gps, s, justify, space)) }
 }
 
 #[cfg_attr(target_os = "windows", hook(bypass))]
 #[cfg_attr(target_os = "linux", hook(by_symbol))]
 fn addst_flag(gps: usize, src: *const u8, a3: usize, a4: usize, flag: u32) {
-  unsafe {
-    let s = CxxString::from_ptr(src);
-    match s.to_bytes_without_nul() {
-      converted => match DICTIONARY.get(converted) {
-        Some(translate) => {
-          let (ptr, len, _) = translate.to_owned().into_raw_parts();
-          let mut cxxstr = CxxString::new(ptr, len - 1);
-          #[cfg(target_os = "linux")]
-          {
-            if cxxstr.len < 16 {
-              cxxstr.ptr = cxxstr.sso.buf.as_mut_ptr();
-            }
-          }
-          original!(gps, cxxstr.as_ptr(), a3, a4, flag)
-        }
-        _ => original!(gps, src, a3, a4, flag),
-      },
-      _ => original!(gps, src, a3, a4, flag),
-    }
-  }
+  unsafe { translate_cxx(src, "addst_flag", |s| original!(gps, s, a3, a4, flag)) }
 }
 
 #[non_exhaustive]
@@ -271,9 +166,9 @@ impl StringEntry {
   pub const STRINGENTRY_FILENAME: u8 = 32;
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn standardstringentry(src: *const u8, maxlen: usize, flag: u8, events_ptr: *const u8, utf: *const u32) -> bool {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn standardstringentry(src: *mut u8, maxlen: usize, flag: u8, events_ptr: *const u8, utf: *const u32) -> bool {
   unsafe {
     let utf_a = std::slice::from_raw_parts_mut(utf as *mut u32, 8);
     #[cfg(target_os = "linux")]
@@ -284,29 +179,40 @@ fn standardstringentry(src: *const u8, maxlen: usize, flag: u8, events_ptr: *con
       );
     }
 
-    let content = CxxString::from_ptr(src);
-    let mut any_valid = false;
-
+    // the lookups come first and nothing is written before they are done, so when one of them panics the
+    // original gets utf and the string as the game passed them
+    let mut mapped = [None; 8];
     for i in 0..8 {
-      if utf_a[i] == 0 || utf_a[i] == 10 || content.size() >= maxlen {
+      if utf_a[i] == 0 || utf_a[i] == 10 {
         break;
       }
-
-      if utf_a[i] > 122 && CONFIG.encoding.utf.contains_key(&utf_a[i]) {
-        let entry = CONFIG.encoding.utf[&utf_a[i]];
-        utf_a[i] = match (flag & StringEntry::CAPS) > 0 {
+      if let Some(&entry) = CONFIG.encoding.utf.get(&utf_a[i]).filter(|_| utf_a[i] > 122) {
+        mapped[i] = Some(match (flag & StringEntry::CAPS) > 0 {
           true => capitalize(entry),
           false => entry,
-        } as u32;
+        });
+      }
+    }
+
+    let any_valid = CxxStrMut::with(src, |content| {
+      let mut any_valid = false;
 
-        if content.size() < maxlen {
-          content.push_back(utf_a[i] as u8);
-          any_valid = true;
+      for i in 0..8 {
+        if utf_a[i] == 0 || utf_a[i] == 10 || content.len() >= maxlen {
+          break;
+        }
+
+        if let Some(symbol) = mapped[i] {
+          utf_a[i] = symbol as u32;
+          if content.len() < maxlen && content.push(symbol) {
+            any_valid = true;
+          }
         }
       }
-    }
+      any_valid
+    });
 
-    any_valid || original!(content.as_ptr(), maxlen, flag, events_ptr, utf_a.as_ptr())
+    any_valid || original!(src, maxlen, flag, events_ptr, utf_a.as_ptr())
   }
 }
 
@@ -319,106 +225,111 @@ fn lowercast(symbol: u8) -> u8 {
   CONFIG.encoding.lowercast[symbol as usize]
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn simplify_string(src: *const u8) {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn simplify_string(src: *mut u8) {
   unsafe {
-    let mut content = CxxString::from_ptr(src);
-    for i in 0..content.len {
-      content[i] = CONFIG.encoding.simplify[content[i] as usize];
-    }
+    CxxStrMut::with(src, |content| {
+      for byte in content.as_bytes_mut() {
+        *byte = CONFIG.encoding.simplify[*byte as usize];
+      }
+    })
   }
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn upper_case_string(src: *const u8) {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn upper_case_string(src: *mut u8) {
   unsafe {
-    let mut content = CxxString::from_ptr(src);
-    for i in 0..content.len {
-      content[i] = CONFIG.encoding.uppercase[content[i] as usize]
-    }
+    CxxStrMut::with(src, |content| {
+      for byte in content.as_bytes_mut() {
+        *byte = CONFIG.encoding.uppercase[*byte as usize];
+      }
+    })
   }
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn lower_case_string(src: *const u8) {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn lower_case_string(src: *mut u8) {
   unsafe {
-    let mut content = CxxString::from_ptr(src);
-    for i in 0..content.len {
-      content[i] = CONFIG.encoding.lowercase[content[i] as usize]
-    }
+    CxxStrMut::with(src, |content| {
+      for byte in content.as_bytes_mut() {
+        *byte = CONFIG.encoding.lowercase[*byte as usize];
+      }
+    })
   }
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn capitalize_string_words(src: *const u8) {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn capitalize_string_words(src: *mut u8) {
   unsafe {
     let mut bracket_count: i32 = 0;
-    let mut content = CxxString::from_ptr(src);
-    for i in 0..content.len {
-      match content[i] {
-        91 => {
-          bracket_count += 1;
+    CxxStrMut::with(src, |content| {
+      for i in 0..content.len() {
+        match content[i] {
+          91 => {
+            bracket_count += 1;
+            continue;
+          }
+          93 => {
+            bracket_count -= 1;
+            continue;
+          }
+          _ => (),
+        };
+        if bracket_count > 0 {
           continue;
         }
-        93 => {
-          bracket_count -= 1;
-          continue;
+        let mut conf = false;
+        if (i > 0 && (content[i - 1] == 32 || content[i - 1] == 34))
+          || (i >= 2 && content[i - 1] == 39 && (content[i - 2] == 32 || content[i - 2] == 44))
+        {
+          conf = true;
+        }
+        if i == 0 || conf {
+          content[i] = CONFIG.encoding.uppercase[content[i] as usize];
         }
-        _ => (),
-      };
-      if bracket_count > 0 {
-        continue;
-      }
-      let mut conf = false;
-      if (i > 0 && content[i - 1] == 32 || content[i - 1] == 34)
-        || (i >= 2 && content[i - 1] == 39 && (content[i - 2] == 32 || content[i - 2] == 44))
-      {
-        conf = true;
-      }
-      if i == 0 || conf {
-        content[i] = CONFIG.encoding.uppercase[content[i] as usize];
       }
-    }
+    })
   }
 }
 
-#[cfg_attr(target_os = "windows", hook(by_offset))]
-#[cfg_attr(target_os = "linux", hook(by_symbol))]
-fn capitalize_string_first_word(src: *const u8) {
+#[cfg_attr(target_os = "windows", hook(by_offset, group = search))]
+#[cfg_attr(target_os = "linux", hook(by_symbol, group = search))]
+fn capitalize_string_first_word(src: *mut u8) {
   unsafe {
     let mut bracket_count: i32 = 0;
-    let mut content = CxxString::from_ptr(src);
-    for i in 0..content.len {
-      match content[i] {
-        91 => {
-          bracket_count += 1;
+    CxxStrMut::with(src, |content| {
+      for i in 0..content.len() {
+        match content[i] {
+          91 => {
+            bracket_count += 1;
+            continue;
+          }
+          93 => {
+            bracket_count -= 1;
+            continue;
+          }
+          _ => (),
+        };
+        if bracket_count > 0 {
           continue;
         }
-        93 => {
-          bracket_count -= 1;
-          continue;
+        let mut conf = false;
+        if (i > 0 && (content[i - 1] == 32 || content[i - 1] == 34))
+          || (i >= 2 && content[i - 1] == 39 && (content[i - 2] == 32 || content[i - 2] == 44))
+        {
+          conf = true;
         }
-        _ => (),
-      };
-      if bracket_count > 0 {
-        continue;
-      }
-      let mut conf = false;
-      if (i > 0 && content[i - 1] == 32 || content[i - 1] == 34)
-        || (i >= 2 && content[i - 1] == 39 && (content[i - 2] == 32 || content[i - 2] == 44))
-      {
-        conf = true;
-      }
-      if i == 0 || conf {
-        content[i] = CONFIG.encoding.uppercase[content[i] as usize];
-        if content[i] != 32 && content[i] != 34 {
-          return;
+        if i == 0 || conf {
+          content[i] = CONFIG.encoding.uppercase[content[i] as usize];
+          if content[i] != 32 && content[i] != 34 {
+            return;
+          }
         }
       }
-    }
+    })
   }
 }
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::config::OffsetsMetadata;
+
+// What identifies a build of the game. The cheap identifiers are read up front; the content hash is only
+// computed when an offsets entry asks for it, hashing a 30+ MB executable takes a while.
+pub struct GameIdentity {
+  path: String,
+  // PE header time_date_stamp
+  pub timestamp: Option<u32>,
+  // GNU build-id note, hex
+  pub build_id: Option<String>,
+  pub size: Option<u64>,
+  hash: OnceLock<Option<u32>>,
+}
+
+impl GameIdentity {
+  pub fn new(path: &str) -> Self {
+    Self {
+      path: String::from(path),
+      timestamp: timestamp(path),
+      build_id: build_id(path),
+      size: std::fs::metadata(path).ok().map(|m| m.len()),
+      hash: OnceLock::new(),
+    }
+  }
+
+  // crc32 of the whole file, what offsets.toml calls checksum on Linux
+  pub fn hash(&self) -> Option<u32> {
+    *self.hash.get_or_init(|| hash(&self.path))
+  }
+
+  // the legacy checksum field: the PE timestamp on Windows, the content hash on Linux
+  pub fn checksum(&self) -> Option<u32> {
+    match cfg!(target_os = "windows") {
+      true => self.timestamp,
+      false => self.hash(),
+    }
+  }
+
+  // every identifier the entry names has to agree, cheap ones are compared first
+  pub fn matches(&self, metadata: &OffsetsMetadata) -> Result<(), String> {
+    if metadata.build_id.is_none() && metadata.size.is_none() && metadata.checksum.is_none() {
+      return Err(String::from("no build-id, size or checksum to match"));
+    }
+    if let Some(build_id) = &metadata.build_id {
+      if self.build_id.as_ref().is_none_or(|b| !b.eq_ignore_ascii_case(build_id)) {
+        return Err(format!("build-id {build_id} does not match"));
+      }
+    }
+    if let Some(size) = metadata.size {
+      if self.size != Some(size) {
+        return Err(format!("size {size} does not match"));
+      }
+    }
+    if let Some(checksum) = metadata.checksum {
+      if self.checksum() != Some(checksum) {
+        return Err(format!("checksum 0x{checksum:x} does not match"));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+impl GameIdentity {
+  pub fn known(build_id: &str, size: u64, hash: Option<u32>) -> Self {
+    Self {
+      path: String::new(),
+      timestamp: None,
+      build_id: Some(String::from(build_id)),
+      size: Some(size),
+      hash: OnceLock::from(hash),
+    }
+  }
+}
+
+// one line for the log and error messages, the hash only when it was needed
+impl fmt::Display for GameIdentity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut parts = Vec::new();
+    if let Some(timestamp) = self.timestamp {
+      parts.push(format!("pe 0x{timestamp:x}"));
+    }
+    if let Some(build_id) = &self.build_id {
+      parts.push(format!("build-id {build_id}"));
+    }
+    if let Some(size) = self.size {
+      parts.push(format!("size {size}"));
+    }
+    if let Some(Some(hash)) = self.hash.get() {
+      parts.push(format!("crc32 0x{hash:x}"));
+    }
+    match parts.is_empty() {
+      true => write!(f, "unknown"),
+      false => write!(f, "{}", parts.join(", ")),
+    }
+  }
+}
+
+#[cfg(target_os = "windows")]
+fn timestamp(path: &str) -> Option<u32> {
+  use exe::{VecPE, PE};
+  let pefile = VecPE::from_disk_file(std::path::Path::new(path)).ok()?;
+  Some(pefile.get_nt_headers_64().ok()?.file_header.time_date_stamp)
+}
+
+#[cfg(target_os = "linux")]
+fn timestamp(_: &str) -> Option<u32> {
+  None
+}
+
+#[cfg(target_os = "linux")]
+fn build_id(path: &str) -> Option<String> {
+  use object::Object;
+  let file = std::fs::File::open(path).ok()?;
+  let data = unsafe { memmap2::Mmap::map(&file).ok()? };
+  let elf = object::File::parse(&*data).ok()?;
+  let id = elf.build_id().ok()??;
+  Some(id.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[cfg(target_os = "windows")]
+fn build_id(_: &str) -> Option<String> {
+  None
+}
+
+#[cfg(target_os = "linux")]
+fn hash(path: &str) -> Option<u32> {
+  checksum::crc::Crc::new(path).checksum().ok().map(|c| c.crc32)
+}
+
+#[cfg(target_os = "windows")]
+fn hash(_: &str) -> Option<u32> {
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn metadata(checksum: Option<u32>, build_id: Option<&str>, size: Option<u64>) -> OffsetsMetadata {
+    OffsetsMetadata {
+      name: String::from("test"),
+      version: String::from("50.13"),
+      checksum,
+      build_id: build_id.map(String::from),
+      size,
+      string_layout: None,
+    }
+  }
+
+  fn identity() -> GameIdentity {
+    GameIdentity::known("3f2a9c", 1024, Some(0x1234))
+  }
+
+  #[test]
+  fn every_named_identifier_has_to_match() {
+    let identity = identity();
+    assert!(identity.matches(&metadata(None, Some("3F2A9C"), None)).is_ok());
+    assert!(identity.matches(&metadata(None, Some("3f2a9c"), Some(1024))).is_ok());
+    assert!(identity.matches(&metadata(None, Some("3f2a9c"), Some(2048))).is_err());
+    assert!(identity.matches(&metadata(None, None, None)).is_err());
+    assert_eq!(
+      identity.matches(&metadata(None, Some("ffff"), None)),
+      Err(String::from("build-id ffff does not match"))
+    );
+  }
+
+  #[test]
+  fn fingerprint_lists_what_is_known() {
+    assert_eq!(identity().to_string(), "build-id 3f2a9c, size 1024, crc32 0x1234");
+    let mut identity = identity();
+    identity.hash = OnceLock::new();
+    assert_eq!(identity.to_string(), "build-id 3f2a9c, size 1024");
+  }
+
+  #[test]
+  fn running_executable_is_identified() {
+    let exe = std::env::current_exe().unwrap();
+    let identity = GameIdentity::new(exe.to_str().unwrap());
+    assert_eq!(identity.size, Some(std::fs::metadata(&exe).unwrap().len()));
+    assert!(identity.hash().is_some() || cfg!(target_os = "windows"));
+  }
+}
@@ -1,50 +1,91 @@
-#![feature(vec_into_raw_parts)]
-
 #[macro_use]
 extern crate serde_derive;
 extern crate toml;
 
+mod bidi;
+mod cache;
+mod capture;
+mod compiled;
 mod config;
 mod constants;
+mod crash;
+mod csv;
 mod cxxstring;
+mod diagnostics;
 mod dictionary;
 mod encoding;
 mod hooks;
+mod identity;
+mod paths;
+mod registry;
+mod reloader;
+mod signatures;
+#[cfg(target_os = "linux")]
+mod symbols;
+mod template;
+mod translator;
 mod utils;
 mod watchdog;
 
 use log::{debug, error, info};
 
 use crate::config::CONFIG;
-use crate::constants::PATH_DICTIONARY;
 use crate::dictionary::DICTIONARY;
 
-#[static_init::constructor]
+#[cfg_attr(not(test), static_init::constructor)]
 #[no_mangle]
 extern "C" fn attach() {
   std::env::set_var("RUST_BACKTRACE", "1");
-  if std::fs::exists(&CONFIG.settings.log_file).unwrap() {
-    std::fs::remove_file(&CONFIG.settings.log_file).expect("unable to remove log file");
+  let _ = std::fs::remove_file(&CONFIG.settings.log_file);
+  let level = utils::log_level(CONFIG.settings.log_level);
+  if simple_logging::log_to_file(&CONFIG.settings.log_file, level).is_err() {
+    simple_logging::log_to_stderr(level);
+    error!("unable to write log file {}, logging to stderr", CONFIG.settings.log_file);
+  }
+  crash::install();
+  paths::PATHS.log();
+  if !std::path::Path::new(&paths::PATHS.config).exists() {
+    info!("{} not found, using default settings", paths::PATHS.config);
   }
-  simple_logging::log_to_file(&CONFIG.settings.log_file, utils::log_level(CONFIG.settings.log_level)).unwrap();
-  if CONFIG.metadata.name != "dfint localization hook" {
-    error!("unable to find config file");
+  if !CONFIG.problems.is_empty() {
+    for problem in &CONFIG.problems {
+      error!("config: {problem}");
+    }
     utils::message_box(
       "dfint hook error",
-      "Unable to find config file, translation unavaible",
-      utils::MessageIconType::Error,
+      format!("Problems found in the installation:\n\n{}", CONFIG.problems.join("\n")).as_str(),
+      utils::MessageIconType::Warning,
     );
-    return;
   }
-  info!("pe checksum: 0x{:x}", CONFIG.offset_metadata.checksum);
-  info!("offsets version: {}", CONFIG.offset_metadata.version);
+  info!("game: {}", CONFIG.identity);
+  match &CONFIG.offset_metadata {
+    Some(metadata) => info!("offsets version: {}", metadata.version),
+    None => info!("offsets version: none"),
+  }
+  info!("string layout: {}", cxxstring::LAYOUT.name());
+  for (candidate, reason) in &CONFIG.rejected {
+    info!("offsets {candidate} rejected, {reason}");
+  }
+  if let Some(discovered) = &CONFIG.discovered {
+    for (name, result) in discovered {
+      match result {
+        Ok(offset) => info!("signature {name}: found at 0x{offset:x}"),
+        Err(err) => error!("signature {name}: {err}, hook disabled"),
+      }
+    }
+  }
   info!("hook version: {}", CONFIG.hook_version);
-  info!("dictionary \"{}\", items {}", PATH_DICTIONARY, DICTIONARY.size());
-  if CONFIG.offset_metadata.name != "not found" {
+  info!(
+    "dictionary \"{}\", items {}, templates {}",
+    DICTIONARY.load().layers().join("\", \""),
+    DICTIONARY.load().size(),
+    DICTIONARY.load().templates()
+  );
+  if CONFIG.offset_metadata.is_some() {
     match unsafe { hooks::attach_all() } {
       Ok(_) => debug!("hooks attached"),
       Err(err) => {
-        error!("unable to attach hooks, {:?}", err);
+        error!("unable to attach hooks, {err:?}");
         utils::message_box(
           "dfint hook error",
           "Unable to attach hooks, translation unavaible",
@@ -56,14 +97,20 @@ extern "C" fn attach() {
     if CONFIG.settings.watchdog {
       watchdog::install();
     }
+    reloader::install();
+    if CONFIG.settings.capture_untranslated {
+      capture::install();
+    }
   }
 }
 
-#[static_init::destructor]
+#[cfg_attr(not(test), static_init::destructor)]
 #[no_mangle]
 extern "C" fn detach() {
   unsafe {
     watchdog::uninstall();
+    reloader::uninstall();
+    capture::uninstall();
     let _ = hooks::disable_all();
     debug!("hooks detached");
   }
@@ -12,6 +12,7 @@ struct Args {
   bypass: bool,
   by_offset: bool,
   by_symbol: bool,
+  group: String,
 }
 
 impl Args {
@@ -22,6 +23,7 @@ impl Args {
     let mut bypass = false;
     let mut by_offset: bool = false;
     let mut by_symbol: bool = false;
+    let mut group = String::from("Translation");
     for arg_pair in args.to_string().split(",") {
       let arg = arg_pair.split("=").collect::<Vec<&str>>();
       match arg[0].trim() {
@@ -31,6 +33,11 @@ impl Args {
         "bypass" => bypass = true,
         "by_offset" => by_offset = true,
         "by_symbol" => by_symbol = true,
+        "group" => match arg[1].trim().replace("\"", "").as_str() {
+          "search" => group = String::from("Search"),
+          "translation" => group = String::from("Translation"),
+          other => panic!("unknown hook group {}", other),
+        },
         _ => (),
       }
     }
@@ -41,6 +48,7 @@ impl Args {
       bypass,
       by_offset,
       by_symbol,
+      group,
     }
   }
 }
@@ -70,7 +78,32 @@ pub fn hook(args: TokenStream, input: TokenStream) -> TokenStream {
     let handle_ident = format_ident!("handle_{}", ident);
     let enable_ident = format_ident!("enable_{}", ident);
     let disable_ident = format_ident!("disable_{}", ident);
+    let registry_ident = format_ident!("HOOK_{}", ident.to_string().to_uppercase());
+    let name = ident.to_string();
+    let group = format_ident!("{}", args.group);
+    let method = format_ident!(
+      "{}",
+      match (args.bypass, args.by_symbol || args.symbol != "") {
+        (true, _) => "Bypass",
+        (_, true) => "Symbol",
+        _ => "Offset",
+      }
+    );
     let ret_type = quote!(#output).to_string();
+    let ret = match &output {
+      syn::ReturnType::Default => quote!(()),
+      syn::ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    let arg_idents = inputs
+      .iter()
+      .map(|arg| match arg {
+        syn::FnArg::Typed(syn::PatType { pat, .. }) => match pat.as_ref() {
+          syn::Pat::Ident(pat) => pat.ident.clone(),
+          _ => panic!("hook arguments have to be plain identifiers"),
+        },
+        _ => panic!("hooks cannot take self"),
+      })
+      .collect::<Vec<syn::Ident>>();
     let inputs_unnamed = quote!(#inputs)
       .to_string()
       .split(",")
@@ -87,17 +120,46 @@ pub fn hook(args: TokenStream, input: TokenStream) -> TokenStream {
       }
 
       pub unsafe fn #enable_ident() -> Result<()> {
-        #handle_ident.enable()?;
-        Ok(())
+        match #handle_ident.enable() {
+          Err(retour::Error::NotInitialized) => Ok(()),
+          result => Ok(result?),
+        }
       }
 
       pub unsafe fn #disable_ident() -> Result<()> {
-        #handle_ident.disable()?;
-        Ok(())
+        match #handle_ident.disable() {
+          Err(retour::Error::NotInitialized) => Ok(()),
+          result => Ok(result?),
+        }
       }
     )
     .to_string();
 
+    // a hook without an offset, e.g. one whose signature was not found, is left detached
+    let by_offset = format!(
+      "{{
+        let Some(offset) = crate::config::CONFIG.offset.as_ref().and_then(|o| o.{ident}) else {{
+          log::warn!(\"no offset for {ident}, hook skipped\");
+          return Ok(());
+        }};
+        std::mem::transmute(utils::address(offset))
+      }}"
+    );
+
+    // the same for a symbol that is missing or ambiguous, the other hooks are attached all the same
+    let by_symbol = format!(
+      "{{
+        let fallback = crate::config::CONFIG.symbol.as_ref().and_then(|s| s.{ident}.as_ref());
+        match symbols::resolve::<fn({inputs_unnamed}) {ret_type}>(\"{ident}\", fallback) {{
+          Ok(target) => target,
+          Err(err) => {{
+            log::warn!(\"{{err}}, hook skipped\");
+            return Ok(());
+          }}
+        }}
+      }}"
+    );
+
     attach = match (args.offset, args.module, args.symbol, args.by_offset, args.by_symbol) {
       (o, _, _, _, _) if o > 0 => attach.replace(
         "target()",
@@ -119,30 +181,9 @@ pub fn hook(args: TokenStream, input: TokenStream) -> TokenStream {
         )
         .as_str(),
       ),
-      (_, _, _, _, bs) if bs => attach.replace(
-        "target()",
-        format!(
-          "utils::symbol_handle::<fn({}) {}>(&CONFIG.symbol.as_ref().unwrap().{}.as_ref().unwrap()[0], &CONFIG.symbol.as_ref().unwrap().{}.as_ref().unwrap()[1])",
-          inputs_unnamed, ret_type, ident.to_string(), ident.to_string()
-        )
-        .as_str(),
-      ),
-      (_, _, _, bo, _) if bo => attach.replace(
-        "target()",
-        format!(
-          "std::mem::transmute(utils::address(CONFIG.offset.as_ref().unwrap().{}.unwrap()))",
-          ident.to_string()
-        )
-        .as_str(),
-      ),
-      (_, _, _, _, _) => attach.replace(
-        "target()",
-        format!(
-          "std::mem::transmute(utils::address(CONFIG.offset.as_ref().unwrap().{}.unwrap()))",
-          ident.to_string()
-        )
-        .as_str(),
-      ),
+      (_, _, _, _, bs) if bs => attach.replace("target()", &by_symbol),
+      (_, _, _, bo, _) if bo => attach.replace("target()", &by_offset),
+      (_, _, _, _, _) => attach.replace("target()", &by_offset),
     };
 
     if args.bypass {
@@ -164,7 +205,40 @@ pub fn hook(args: TokenStream, input: TokenStream) -> TokenStream {
 
     let result = quote!(
       static_detour! { static #handle_ident: unsafe #abi fn() #output; }
-      #vis #unsafety #constness fn #ident(#inputs) #output #block
+      #[linkme::distributed_slice(crate::registry::HOOKS)]
+      static #registry_ident: crate::registry::Hook = crate::registry::Hook::new(
+        #name,
+        crate::registry::Group::#group,
+        crate::registry::Method::#method,
+        #attach_ident,
+        #enable_ident,
+        #disable_ident,
+        || #handle_ident.is_enabled(),
+      );
+      // a panic must not unwind into the game, the original function is called instead with the arguments
+      // the detour got, and an original the body already called is not called again; bodies that write through
+      // pointer arguments do it after every step that can panic, as standardstringentry does
+      #vis #unsafety #constness fn #ident(#inputs) #output {
+        let called: std::cell::Cell<Option<#ret>> = std::cell::Cell::new(None);
+        #[allow(unused_macros)]
+        macro_rules! call_through {
+          ($($arg:expr),*) => {{
+            let result = #handle_ident.call($($arg),*);
+            called.set(Some(result));
+            result
+          }};
+        }
+        match crate::registry::guard(|| #block) {
+          Ok(result) => result,
+          Err(_) => {
+            #registry_ident.panicked(crate::config::CONFIG.settings.hook_panic_limit);
+            match called.take() {
+              Some(result) => result,
+              None => unsafe { #handle_ident.call(#(#arg_idents),*) },
+            }
+          }
+        }
+      }
     );
 
     return format!(
@@ -172,7 +246,7 @@ pub fn hook(args: TokenStream, input: TokenStream) -> TokenStream {
       attach.to_string(),
       result
         .to_string()
-        .replace("original!", format!("handle_{}.call", ident.to_string()).as_str())
+        .replace("original!", "call_through!")
         .replace("fn()", format!("fn({})", inputs_unnamed).as_str())
     )
     .parse()
@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::constants::{
+  DIR_DATA, DIR_DICTIONARY_LAYERS, DIR_OFFSETS, ENV_CONFIG, ENV_DATA, ENV_DICTIONARY, ENV_ENCODING, ENV_OFFSETS,
+  FILE_CONFIG, FILE_DICTIONARY, FILE_DICTIONARY_COMPILED, FILE_DICTIONARY_USER, FILE_ENCODING, FILE_OFFSETS,
+  FILE_UNTRANSLATED, FILE_UNTRANSLATED_STATS, PATH_EXE,
+};
+
+#[static_init::dynamic(lazy)]
+pub static PATHS: Paths = Paths::resolve();
+
+// Where the hook finds its files. Each path comes from an environment variable, then the [paths] section
+// of config.toml, then the data directory next to the game executable. Relative paths are taken from the
+// game directory, not the working directory, which depends on how the game was launched.
+pub struct Paths {
+  pub exe: String,
+  pub game: String,
+  pub data: String,
+  pub config: String,
+  pub offsets: String,
+  pub offsets_dir: String,
+  pub encoding: String,
+  pub dictionary: String,
+  pub dictionary_layers: String,
+  pub dictionary_user: String,
+  pub dictionary_compiled: String,
+  pub untranslated: String,
+  pub untranslated_stats: String,
+  // (name, path, where it came from) for the startup log
+  origins: Vec<(&'static str, String, &'static str)>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PathsSection {
+  pub data: Option<String>,
+  pub offsets: Option<String>,
+  pub encoding: Option<String>,
+  pub dictionary: Option<String>,
+}
+
+fn join(base: &Path, path: &str) -> String {
+  base.join(path).to_string_lossy().into_owned()
+}
+
+fn game_dir(exe: &Path) -> PathBuf {
+  exe.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf)
+}
+
+// the config file cannot name itself, so only the environment can move it
+fn config_path(game: &Path, env: &impl Fn(&str) -> Option<String>) -> (String, &'static str) {
+  match (env(ENV_CONFIG), env(ENV_DATA)) {
+    (Some(config), _) => (join(game, &config), "environment"),
+    (None, Some(data)) => (join(&game.join(data), FILE_CONFIG), "environment"),
+    (None, None) => (join(&game.join(DIR_DATA), FILE_CONFIG), "default"),
+  }
+}
+
+impl Paths {
+  fn resolve() -> Self {
+    let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from(PATH_EXE));
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let (config, _) = config_path(&game_dir(&exe), &env);
+    // problems are reported by CONFIG, which is loaded from the path resolved here
+    let table = std::fs::read_to_string(&config)
+      .ok()
+      .and_then(|content| content.parse::<toml::Table>().ok())
+      .unwrap_or_default();
+    let section = config::section(&table, "paths", &mut Vec::new());
+    Self::new(&exe, env, section)
+  }
+
+  pub fn new(exe: &Path, env: impl Fn(&str) -> Option<String>, section: PathsSection) -> Self {
+    let game = game_dir(exe);
+    let (config, config_origin) = config_path(&game, &env);
+    let mut origins = vec![("config", config.clone(), config_origin)];
+    let mut pick = |name: &'static str, var: &str, configured: Option<String>, default: String| {
+      let (path, origin) = match (env(var), configured) {
+        (Some(path), _) => (join(&game, &path), "environment"),
+        (None, Some(path)) => (join(&game, &path), "config"),
+        (None, None) => (default, "default"),
+      };
+      origins.push((name, path.clone(), origin));
+      path
+    };
+
+    let data = pick("data", ENV_DATA, section.data, join(&game, DIR_DATA));
+    let data_dir = PathBuf::from(&data);
+    let offsets = pick("offsets", ENV_OFFSETS, section.offsets, join(&data_dir, FILE_OFFSETS));
+    let encoding = pick("encoding", ENV_ENCODING, section.encoding, join(&data_dir, FILE_ENCODING));
+    let dictionary = pick("dictionary", ENV_DICTIONARY, section.dictionary, join(&data_dir, FILE_DICTIONARY));
+
+    Self {
+      exe: exe.to_string_lossy().into_owned(),
+      game: game.to_string_lossy().into_owned(),
+      offsets_dir: join(&data_dir, DIR_OFFSETS),
+      dictionary_layers: join(&data_dir, DIR_DICTIONARY_LAYERS),
+      dictionary_user: join(&data_dir, FILE_DICTIONARY_USER),
+      dictionary_compiled: join(&data_dir, FILE_DICTIONARY_COMPILED),
+      untranslated: join(&data_dir, FILE_UNTRANSLATED),
+      untranslated_stats: join(&data_dir, FILE_UNTRANSLATED_STATS),
+      data,
+      config,
+      offsets,
+      encoding,
+      dictionary,
+      origins,
+    }
+  }
+
+  // paths from config.toml, like the log file, are relative to the game directory as well
+  pub fn locate(&self, path: &str) -> String {
+    join(Path::new(&self.game), path)
+  }
+
+  pub fn log(&self) {
+    log::info!("game executable: {}", self.exe);
+    for (name, path, origin) in &self.origins {
+      log::info!("{name} path: {path} ({origin})");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn environment_wins_over_config_over_defaults() {
+    let exe = Path::new("/games/df/dwarfort");
+    let env = |name: &str| match name {
+      ENV_DATA => Some(String::from("/srv/dfint-ru")),
+      ENV_OFFSETS => Some(String::from("offsets/50.13.toml")),
+      _ => None,
+    };
+    let section = PathsSection {
+      data: Some(String::from("ignored")),
+      dictionary: Some(String::from("dfint-de/dictionary.csv")),
+      ..Default::default()
+    };
+    let paths = Paths::new(exe, env, section);
+
+    assert_eq!(paths.config, "/srv/dfint-ru/config.toml");
+    assert_eq!(paths.offsets, "/games/df/offsets/50.13.toml");
+    assert_eq!(paths.dictionary, "/games/df/dfint-de/dictionary.csv");
+    assert_eq!(paths.encoding, "/srv/dfint-ru/encoding.toml");
+    assert_eq!(paths.dictionary_user, "/srv/dfint-ru/dictionary.user.csv");
+    assert_eq!(paths.locate("./dfint-data/dfint-log.log"), "/games/df/./dfint-data/dfint-log.log");
+  }
+
+  #[test]
+  fn defaults_are_next_to_the_executable() {
+    let paths = Paths::new(Path::new("/games/df/dwarfort"), |_| None, PathsSection::default());
+    assert_eq!(paths.config, "/games/df/dfint-data/config.toml");
+    assert_eq!(paths.offsets, "/games/df/dfint-data/offsets.toml");
+    assert_eq!(paths.offsets_dir, "/games/df/dfint-data/offsets");
+    assert_eq!(paths.dictionary_compiled, "/games/df/dfint-data/dictionary.bin");
+    assert!(paths.origins.iter().all(|(_, _, origin)| *origin == "default"));
+  }
+}
@@ -0,0 +1,275 @@
+use anyhow::Result;
+use linkme::distributed_slice;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+// Every #[hook] function adds itself here, so attaching, toggling and reporting need no hand-written lists.
+#[distributed_slice]
+pub static HOOKS: [Hook];
+
+thread_local! {
+  // message and backtrace of the last panic on this thread, taken by the hook that caught it
+  static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+  // set while a detour body runs
+  static IN_DETOUR: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Group {
+  Translation,
+  Search,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+  #[cfg_attr(target_os = "linux", allow(dead_code))]
+  Offset,
+  Symbol,
+  // not hooked on this platform
+  Bypass,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+  Detached,
+  // attached without an address, e.g. a signature that was not found
+  Unresolved,
+  Enabled,
+  Disabled,
+  // attaching failed, or switched off after panicking too often
+  Failed,
+}
+
+impl State {
+  fn from_u8(value: u8) -> Self {
+    match value {
+      1 => State::Unresolved,
+      2 => State::Enabled,
+      3 => State::Disabled,
+      4 => State::Failed,
+      _ => State::Detached,
+    }
+  }
+}
+
+impl State {
+  pub fn name(&self) -> &'static str {
+    match self {
+      State::Detached => "detached",
+      State::Unresolved => "unresolved",
+      State::Enabled => "enabled",
+      State::Disabled => "disabled",
+      State::Failed => "failed",
+    }
+  }
+}
+
+impl fmt::Display for State {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+pub struct Hook {
+  pub name: &'static str,
+  pub group: Group,
+  pub method: Method,
+  attach: unsafe fn() -> Result<()>,
+  enable: unsafe fn() -> Result<()>,
+  disable: unsafe fn() -> Result<()>,
+  is_enabled: fn() -> bool,
+  state: AtomicU8,
+  panics: AtomicU32,
+}
+
+impl Hook {
+  pub const fn new(
+    name: &'static str,
+    group: Group,
+    method: Method,
+    attach: unsafe fn() -> Result<()>,
+    enable: unsafe fn() -> Result<()>,
+    disable: unsafe fn() -> Result<()>,
+    is_enabled: fn() -> bool,
+  ) -> Self {
+    Self {
+      name,
+      group,
+      method,
+      attach,
+      enable,
+      disable,
+      is_enabled,
+      state: AtomicU8::new(State::Detached as u8),
+      panics: AtomicU32::new(0),
+    }
+  }
+
+  pub fn state(&self) -> State {
+    State::from_u8(self.state.load(Ordering::Relaxed))
+  }
+
+  fn set(&self, state: State) {
+    self.state.store(state as u8, Ordering::Relaxed);
+  }
+
+  pub unsafe fn attach(&self) -> Result<()> {
+    if self.method == Method::Bypass {
+      return Ok(());
+    }
+    match (self.attach)() {
+      Ok(_) if (self.is_enabled)() => self.set(State::Enabled),
+      Ok(_) => self.set(State::Unresolved),
+      Err(err) => {
+        self.set(State::Failed);
+        return Err(err);
+      }
+    }
+    Ok(())
+  }
+
+  // only hooks that were attached can be toggled
+  pub unsafe fn enable(&self) -> Result<()> {
+    if self.state() == State::Disabled {
+      (self.enable)()?;
+      self.set(State::Enabled);
+    }
+    Ok(())
+  }
+
+  pub unsafe fn disable(&self) -> Result<()> {
+    if self.state() == State::Enabled {
+      (self.disable)()?;
+      self.set(State::Disabled);
+    }
+    Ok(())
+  }
+
+  pub fn panics(&self) -> u32 {
+    self.panics.load(Ordering::Relaxed)
+  }
+
+  // called by the detour after catching a panic, before it calls the original function;
+  // a limit of 0 never switches the hook off
+  pub fn panicked(&self, limit: u32) {
+    let count = self.panics.fetch_add(1, Ordering::Relaxed) + 1;
+    let report = LAST_PANIC.with(|p| p.take()).unwrap_or_else(|| String::from("no details"));
+    log::error!("hook {}: panic #{count}, original function called instead\n{report}", self.name);
+    if limit == 0 || count < limit || self.state() != State::Enabled {
+      return;
+    }
+    match unsafe { (self.disable)() } {
+      Ok(_) => {
+        self.set(State::Failed);
+        log::error!("hook {}: disabled after {count} panics", self.name);
+      }
+      Err(err) => log::error!("hook {}: unable to disable, {err:?}", self.name),
+    }
+  }
+}
+
+// runs a detour body, its panics are caught and handed to Hook::panicked instead of unwinding into the game
+pub fn guard<T>(body: impl FnOnce() -> T) -> std::thread::Result<T> {
+  let outer = IN_DETOUR.replace(true);
+  let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(body));
+  IN_DETOUR.set(outer);
+  result
+}
+
+pub fn in_detour() -> bool {
+  IN_DETOUR.get()
+}
+
+// the panic hook keeps location and backtrace here, the stack is gone once the panic is caught
+pub fn note_panic(report: String) {
+  LAST_PANIC.with(|p| *p.borrow_mut() = Some(report));
+}
+
+// registered hooks by name, the link order is arbitrary
+pub fn sorted() -> Vec<&'static Hook> {
+  let mut hooks: Vec<&Hook> = HOOKS.iter().collect();
+  hooks.sort_unstable_by_key(|hook| hook.name);
+  hooks
+}
+
+// one line per hook that exists on this platform
+pub fn status() -> Vec<(&'static str, Group, Method, State, u32)> {
+  sorted()
+    .into_iter()
+    .filter(|hook| hook.method != Method::Bypass)
+    .map(|hook| (hook.name, hook.group, hook.method, hook.state(), hook.panics()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get(name: &str) -> Option<&'static Hook> {
+    HOOKS.iter().find(|hook| hook.name == name)
+  }
+
+  #[test]
+  fn every_hook_is_registered_once() {
+    let names: Vec<&str> = sorted().iter().map(|hook| hook.name).collect();
+    assert_eq!(names.len(), 14);
+    assert!(names.windows(2).all(|pair| pair[0] != pair[1]));
+    assert_eq!(get("addst").unwrap().group, Group::Translation);
+    assert_eq!(get("standardstringentry").unwrap().group, Group::Search);
+    assert_eq!(HOOKS.iter().filter(|hook| hook.group == Group::Search).count(), 6);
+    assert!(get("missing").is_none());
+  }
+
+  #[test]
+  fn unattached_hooks_are_not_toggled() {
+    let hook = get("addst").unwrap();
+    assert_eq!(hook.state(), State::Detached);
+    unsafe {
+      hook.enable().unwrap();
+      hook.disable().unwrap();
+    }
+    assert_eq!(hook.state(), State::Detached);
+    assert!(status().iter().all(|(.., state, panics)| *state == State::Detached && *panics == 0));
+  }
+
+  #[test]
+  fn hooks_that_keep_panicking_are_switched_off() {
+    static DISABLED: AtomicU32 = AtomicU32::new(0);
+    unsafe fn ok() -> Result<()> {
+      Ok(())
+    }
+    unsafe fn disable() -> Result<()> {
+      DISABLED.fetch_add(1, Ordering::Relaxed);
+      Ok(())
+    }
+    let hook = Hook::new("test", Group::Translation, Method::Offset, ok, ok, disable, || true);
+    unsafe { hook.attach().unwrap() };
+    assert_eq!(hook.state(), State::Enabled);
+
+    hook.panicked(2);
+    assert_eq!((hook.state(), hook.panics()), (State::Enabled, 1));
+    hook.panicked(2);
+    assert_eq!((hook.state(), hook.panics()), (State::Failed, 2));
+    assert_eq!(DISABLED.load(Ordering::Relaxed), 1);
+
+    // failed hooks stay off when the watchdog turns everything back on
+    unsafe { hook.enable().unwrap() };
+    assert_eq!(hook.state(), State::Failed);
+
+    let never = Hook::new("never", Group::Translation, Method::Offset, ok, ok, disable, || true);
+    unsafe { never.attach().unwrap() };
+    for _ in 0..5 {
+      never.panicked(0);
+    }
+    assert_eq!(never.state(), State::Enabled);
+  }
+
+  #[test]
+  fn guard_catches_panics_of_detour_bodies() {
+    assert!(!in_detour());
+    assert!(guard(in_detour).unwrap());
+    assert!(guard(|| -> u8 { panic!("detour") }).is_err());
+    assert!(!in_detour());
+  }
+}
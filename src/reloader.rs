@@ -0,0 +1,53 @@
+use std::time::SystemTime;
+
+use crate::dictionary::{self, DICTIONARY};
+
+#[static_init::dynamic]
+static mut KILL: bool = false;
+
+fn modified(path: &str) -> Option<(SystemTime, u64)> {
+  let metadata = std::fs::metadata(path).ok()?;
+  Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// layers can appear and disappear too, so the layer list is part of the stamp
+fn stamp() -> Vec<(String, Option<(SystemTime, u64)>)> {
+  dictionary::layers()
+    .into_iter()
+    .map(|path| {
+      let modified = modified(&path);
+      (path, modified)
+    })
+    .collect()
+}
+
+pub fn install() {
+  std::thread::spawn(move || {
+    let mut last = stamp();
+
+    while !*KILL.read() {
+      std::thread::sleep(std::time::Duration::from_millis(1000));
+      let current = stamp();
+      if current[0].1.is_none() || current == last {
+        continue;
+      }
+      // editors write in several steps, wait until the files settle
+      std::thread::sleep(std::time::Duration::from_millis(250));
+      last = stamp();
+      match dictionary::reload() {
+        Ok(diff) => log::info!(
+          "dictionary reloaded, items {}, added {}, removed {}, changed {}",
+          DICTIONARY.load().size(),
+          diff.added,
+          diff.removed,
+          diff.changed
+        ),
+        Err(err) => log::error!("unable to reload dictionary, {err:?}"),
+      }
+    }
+  });
+}
+
+pub fn uninstall() {
+  *KILL.write() = true;
+}
@@ -0,0 +1,23 @@
+// Real std::string objects for the CxxString layout tests. Nothing here is called outside of tests,
+// so the linker leaves it out of the hook itself.
+#include <cstddef>
+#include <string>
+
+extern "C" {
+
+size_t shim_string_sizeof() { return sizeof(std::string); }
+
+std::string *shim_string_new(const char *data, size_t len) { return new std::string(data, len); }
+
+void shim_string_delete(std::string *s) { delete s; }
+
+const char *shim_string_data(const std::string *s) { return s->data(); }
+
+size_t shim_string_size(const std::string *s) { return s->size(); }
+
+size_t shim_string_capacity(const std::string *s) { return s->capacity(); }
+
+void shim_string_push_back(std::string *s, char c) { s->push_back(c); }
+
+void shim_string_pop_back(std::string *s) { s->pop_back(); }
+}
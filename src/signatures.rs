@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+
+use crate::config::{OffsetsValues, SignaturesValues};
+use crate::utils;
+
+// Byte patterns for finding hooked functions in builds offsets.toml does not know, e.g.
+//   addst = "48 89 5c 24 ?? 57 48 83 ec ?? 48 8b f9"
+// ?? matches any byte, the match must be unique and its first byte is the function address
+
+pub fn parse(pattern: &str) -> Result<Vec<Option<u8>>> {
+  let bytes = pattern
+    .split_whitespace()
+    .map(|b| match b {
+      "?" | "??" => Ok(None),
+      _ => u8::from_str_radix(b, 16)
+        .map(Some)
+        .map_err(|_| anyhow!("invalid byte \"{b}\"")),
+    })
+    .collect::<Result<Vec<_>>>()?;
+  if !bytes.iter().any(Option::is_some) {
+    return Err(anyhow!("pattern has no fixed bytes"));
+  }
+  Ok(bytes)
+}
+
+pub fn find(haystack: &[u8], pattern: &[Option<u8>]) -> Vec<usize> {
+  if pattern.is_empty() || haystack.len() < pattern.len() {
+    return Vec::new();
+  }
+  // anchor on the first fixed byte, most positions are rejected by it alone
+  let (anchor, first) = pattern.iter().enumerate().find_map(|(i, b)| b.map(|b| (i, b))).unwrap_or((0, 0));
+  (0..=haystack.len() - pattern.len())
+    .filter(|&i| haystack[i + anchor] == first)
+    .filter(|&i| pattern.iter().zip(&haystack[i..]).all(|(p, h)| p.is_none_or(|p| p == *h)))
+    .collect()
+}
+
+// executable sections of the game image as it is mapped in memory
+#[cfg(target_os = "windows")]
+unsafe fn code_sections() -> Vec<&'static [u8]> {
+  let base = *utils::MODULE as *const u8;
+  let read_u16 = |p: *const u8| std::ptr::read_unaligned(p as *const u16) as usize;
+  let read_u32 = |p: *const u8| std::ptr::read_unaligned(p as *const u32) as usize;
+  let nt = base.add(read_u32(base.add(0x3c)));
+  let count = read_u16(nt.add(6));
+  let table = nt.add(24 + read_u16(nt.add(20)));
+  (0..count)
+    .map(|i| table.add(i * 40))
+    .filter(|section| read_u32(section.add(36)) & 0x20000000 != 0)
+    .map(|section| std::slice::from_raw_parts(base.add(read_u32(section.add(12))), read_u32(section.add(8))))
+    .collect()
+}
+
+// Linux hooks are looked up by name in the symbol tables and never use discovered offsets,
+// so Config::new does not scan there
+#[cfg(target_os = "linux")]
+unsafe fn code_sections() -> Vec<&'static [u8]> {
+  Vec::new()
+}
+
+// offset of the single match relative to the module, the same thing offsets.toml holds
+fn scan(sections: &[&[u8]], pattern: &str) -> Result<usize> {
+  let pattern = parse(pattern)?;
+  let found: Vec<usize> = sections
+    .iter()
+    .flat_map(|section| find(section, &pattern).into_iter().map(|i| section.as_ptr() as usize + i))
+    .collect();
+  match found.as_slice() {
+    [address] => Ok(address - *utils::MODULE),
+    [] => Err(anyhow!("not found")),
+    _ => Err(anyhow!("{} matches", found.len())),
+  }
+}
+
+// builds offsets for the running image, along with the outcome for every hook that has a signature
+pub fn discover(signatures: &SignaturesValues) -> (OffsetsValues, Vec<(&'static str, Result<usize>)>) {
+  let sections = unsafe { code_sections() };
+  let mut report = Vec::new();
+  let mut find = |name: &'static str, pattern: &Option<String>| {
+    let result = scan(&sections, pattern.as_ref()?);
+    let offset = result.as_ref().ok().copied();
+    report.push((name, result));
+    offset
+  };
+  let offsets = OffsetsValues {
+    string_copy_n: find("string_copy_n", &signatures.string_copy_n),
+    string_append_n: find("string_append_n", &signatures.string_append_n),
+    std_string_ctor: find("std_string_ctor", &signatures.std_string_ctor),
+    addst: find("addst", &signatures.addst),
+    addst_top: find("addst_top", &signatures.addst_top),
+    addst_flag: find("addst_flag", &signatures.addst_flag),
+    standardstringentry: find("standardstringentry", &signatures.standardstringentry),
+    simplify_string: find("simplify_string", &signatures.simplify_string),
+    upper_case_string: find("upper_case_string", &signatures.upper_case_string),
+    lower_case_string: find("lower_case_string", &signatures.lower_case_string),
+    capitalize_string_words: find("capitalize_string_words", &signatures.capitalize_string_words),
+    capitalize_string_first_word: find("capitalize_string_first_word", &signatures.capitalize_string_first_word),
+    // data, not code, so there is nothing to scan for
+    utf_input: None,
+    operator_new: find("operator_new", &signatures.operator_new),
+    operator_delete: find("operator_delete", &signatures.operator_delete),
+  };
+  (offsets, report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn wildcards_match_any_byte() {
+    let pattern = parse("48 89 ?? 24 ? 57").unwrap();
+    let haystack = [0x90, 0x48, 0x89, 0x5c, 0x24, 0x08, 0x57, 0x48, 0x89, 0x4c, 0x24, 0x10, 0x57, 0x48, 0x89];
+    assert_eq!(find(&haystack, &pattern), vec![1, 7]);
+    assert_eq!(find(&haystack[..12], &pattern), vec![1]);
+    assert_eq!(find(&[0x48], &pattern), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn leading_wildcards_are_anchored_on_the_first_fixed_byte() {
+    let pattern = parse("?? ?? c3").unwrap();
+    assert_eq!(find(&[0xc3, 0x00, 0x01, 0xc3], &pattern), vec![1]);
+  }
+
+  #[test]
+  fn invalid_patterns_are_rejected() {
+    assert!(parse("48 zz").is_err());
+    assert!(parse("?? ??").is_err());
+    assert!(parse("").is_err());
+  }
+
+  #[test]
+  fn scan_requires_a_single_match() {
+    let code: &[u8] = &[0x55, 0x48, 0x89, 0xe5, 0xc3, 0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3];
+    let sections = [code];
+    assert_eq!(scan(&sections, "48 89 e5 5d").unwrap(), code.as_ptr() as usize + 6 - *utils::MODULE);
+    assert!(scan(&sections, "55 48 89 e5").is_err());
+    assert!(scan(&sections, "90 90").is_err());
+  }
+}
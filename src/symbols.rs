@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Result};
+use memmap2::Mmap;
+use object::{Object, ObjectKind, ObjectSegment, ObjectSymbol};
+use std::collections::HashMap;
+
+use crate::utils;
+
+// Linux hooks are found by their demangled C++ signature in the ELF symbol tables of the game and its C++ library,
+// so they survive a new release without collecting mangled names again. The [symbols] section of offsets.toml
+// is only used for hooks that are not found this way.
+
+// module name for the C++ library the game links, libstdc++ or libc++ depending on the string layout
+const STD: &str = "std";
+
+struct Wanted {
+  hook: &'static str,
+  module: &'static str,
+  name: &'static str,
+  // parameter list as demangled, None accepts any overload as long as there is only one
+  params: Option<&'static str>,
+}
+
+const fn wanted(hook: &'static str, module: &'static str, name: &'static str, params: Option<&'static str>) -> Wanted {
+  Wanted {
+    hook,
+    module,
+    name,
+    params,
+  }
+}
+
+const TABLE: &[Wanted] = &[
+  wanted("addst", "self", "graphicst::addst", Some("std::string const&, justification, int")),
+  wanted("addst_top", "self", "graphicst::addst_top", Some("std::string const&, justification, int")),
+  wanted("addst_flag", "self", "graphicst::addst_flag", None),
+  wanted("standardstringentry", "self", "standardstringentry", None),
+  wanted("simplify_string", "self", "simplify_string", Some("std::string&")),
+  wanted("upper_case_string", "self", "upper_case_string", Some("std::string&")),
+  wanted("lower_case_string", "self", "lower_case_string", Some("std::string&")),
+  wanted("capitalize_string_words", "self", "capitalize_string_words", Some("std::string&")),
+  wanted("capitalize_string_first_word", "self", "capitalize_string_first_word", Some("std::string&")),
+  wanted("std_string_append", STD, "std::string::append", Some("char const*")),
+  wanted("std_string_assign", STD, "std::string::assign", Some("char const*")),
+  wanted("enabler", "self", "enabler", None),
+];
+
+#[static_init::dynamic(lazy)]
+static RESOLVED: HashMap<&'static str, usize> = resolve_all();
+
+fn normalize(demangled: &str) -> String {
+  demangled
+    .replace("std::__cxx11::basic_string<char, std::char_traits<char>, std::allocator<char> >", "std::string")
+    .replace("std::__cxx11::", "std::")
+    .replace("std::__1::basic_string<char, std::__1::char_traits<char>, std::__1::allocator<char> >", "std::string")
+    .replace("std::__1::", "std::")
+}
+
+// libstdc++ still exports the copy-on-write std::string of the old ABI next to std::__cxx11::basic_string, with the
+// same demangled names once normalized; the game uses the C++11 one, so the old symbols are left out
+fn copy_on_write(demangled: &str) -> bool {
+  demangled.contains("std::string") || demangled.contains("std::basic_string<")
+}
+
+// the C++ library the game was built against, told by the mangled names it imports: libc++ keeps everything in
+// std::__1, libstdc++ strings are in std::__cxx11
+fn imported_library(data: &[u8]) -> Option<&'static str> {
+  let file = object::File::parse(data).ok()?;
+  let imports: Vec<&str> =
+    file.dynamic_symbols().filter(|symbol| symbol.is_undefined()).filter_map(|symbol| symbol.name().ok()).collect();
+  if imports.iter().any(|name| name.contains("St3__1")) {
+    Some("libc++")
+  } else if imports.iter().any(|name| name.contains("St7__cxx11")) {
+    Some("libstdc++")
+  } else {
+    None
+  }
+}
+
+pub fn string_layout() -> Option<&'static str> {
+  let (path, _) = module("self")?;
+  let file = std::fs::File::open(path).ok()?;
+  let data = unsafe { Mmap::map(&file).ok()? };
+  imported_library(&data)
+}
+
+// the library behind the std::string layout in use, its exports are named after that layout
+fn std_library() -> &'static str {
+  match crate::cxxstring::LAYOUT.name() {
+    "libc++" => "libc++.so.1",
+    _ => "libstdc++.so.6",
+  }
+}
+
+// qualified name and parameter list, plain C symbols have no parameters
+fn split(demangled: &str) -> (&str, Option<&str>) {
+  match (demangled.find('('), demangled.rfind(')')) {
+    (Some(open), Some(close)) if open < close => (&demangled[..open], Some(&demangled[open + 1..close])),
+    _ => (demangled, None),
+  }
+}
+
+fn short_name(name: &str) -> &str {
+  name.rsplit("::").next().unwrap_or(name)
+}
+
+// address each wanted symbol has in the file, plus the base it is loaded at
+fn find<'a>(data: &[u8], base: usize, wanted: &[&'a Wanted]) -> Result<HashMap<&'a str, Result<(String, usize)>>> {
+  let file = object::File::parse(data)?;
+  let base = match file.kind() {
+    ObjectKind::Executable => 0,
+    _ => base - (file.segments().map(|s| s.address()).min().unwrap_or(0) as usize & !0xfff),
+  };
+  let mut found: HashMap<&str, Vec<(String, usize)>> = HashMap::new();
+  for symbol in file.symbols().chain(file.dynamic_symbols()) {
+    let Ok(raw) = symbol.name() else {
+      continue;
+    };
+    if symbol.is_undefined() || !wanted.iter().any(|w| raw.contains(short_name(w.name))) {
+      continue;
+    }
+    let demangled = match cpp_demangle::Symbol::new(raw).ok().and_then(|s| s.demangle().ok()) {
+      Some(demangled) if copy_on_write(&demangled) => continue,
+      Some(demangled) => normalize(&demangled),
+      None => String::from(raw),
+    };
+    let (name, params) = split(&demangled);
+    for w in wanted {
+      if name == w.name && w.params.is_none_or(|p| params == Some(p)) {
+        let address = base + symbol.address() as usize;
+        let matches = found.entry(w.hook).or_default();
+        // symtab and dynsym often list the same function
+        if !matches.iter().any(|(_, a)| *a == address) {
+          matches.push((demangled.clone(), address));
+        }
+      }
+    }
+  }
+  Ok(
+    wanted
+      .iter()
+      .map(|w| {
+        let result = match found.remove(w.hook) {
+          Some(mut matches) if matches.len() == 1 => Ok(matches.remove(0)),
+          Some(matches) => Err(anyhow!("{} overloads of {}", matches.len(), w.name)),
+          None => Err(anyhow!("{} not found", w.name)),
+        };
+        (w.hook, result)
+      })
+      .collect(),
+  )
+}
+
+// file and load address of a module of the running process
+fn module(name: &str) -> Option<(String, usize)> {
+  let path = match name {
+    "self" => std::env::current_exe().ok()?.to_string_lossy().into_owned(),
+    _ => String::new(),
+  };
+  let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+  maps.lines().find_map(|line| {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let file = *fields.get(5)?;
+    let matches = match name {
+      "self" => file == path,
+      _ => file.rsplit('/').next()?.starts_with(name),
+    };
+    if !matches || usize::from_str_radix(fields[2], 16).ok()? != 0 {
+      return None;
+    }
+    let start = usize::from_str_radix(fields[0].split_once('-')?.0, 16).ok()?;
+    Some((String::from(file), start))
+  })
+}
+
+fn resolve_all() -> HashMap<&'static str, usize> {
+  let mut resolved = HashMap::new();
+  let mut modules: Vec<&str> = TABLE.iter().map(|w| w.module).collect();
+  modules.sort_unstable();
+  modules.dedup();
+  for name in modules {
+    let wanted: Vec<&Wanted> = TABLE.iter().filter(|w| w.module == name).collect();
+    let name = match name {
+      STD => std_library(),
+      name => name,
+    };
+    let Some((path, base)) = module(name) else {
+      log::warn!("elf symbols: module {name} is not loaded");
+      continue;
+    };
+    let found = std::fs::File::open(&path)
+      .and_then(|file| unsafe { Mmap::map(&file) })
+      .map_err(Into::into)
+      .and_then(|data| find(&data, base, &wanted));
+    match found {
+      Ok(found) => {
+        for (hook, result) in found {
+          match result {
+            Ok((signature, address)) => {
+              log::debug!("elf symbol {hook}: {signature} at 0x{address:x}");
+              resolved.insert(hook, address);
+            }
+            Err(err) => log::warn!("elf symbol {hook}: {err}"),
+          }
+        }
+      }
+      Err(err) => log::warn!("elf symbols: unable to read {path}, {err}"),
+    }
+  }
+  resolved
+}
+
+// the address from the ELF symbol tables, or the [module, symbol] pair from offsets.toml when that fails
+pub unsafe fn resolve<T>(hook: &str, fallback: Option<&Vec<String>>) -> Result<T> {
+  if let Some(&address) = RESOLVED.get(hook) {
+    return Ok(std::mem::transmute_copy::<usize, T>(&address));
+  }
+  match fallback.map(Vec::as_slice) {
+    Some([module, symbol]) => {
+      log::info!("symbol {hook}: using {module} {symbol} from offsets");
+      Ok(utils::symbol_handle::<T>(module, symbol))
+    }
+    _ => Err(anyhow!("no symbol for {hook}")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn signatures_are_normalized() {
+    let demangled = cpp_demangle::Symbol::new(
+      "_ZN9graphicst5addstERKNSt7__cxx1112basic_stringIcSt11char_traitsIcESaIcEEE13justificationi",
+    )
+    .unwrap()
+    .demangle()
+    .unwrap();
+    let demangled = normalize(&demangled);
+    assert_eq!(demangled, "graphicst::addst(std::string const&, justification, int)");
+    assert_eq!(split(&demangled), ("graphicst::addst", Some("std::string const&, justification, int")));
+
+    let demangled = cpp_demangle::Symbol::new("_ZNSt7__cxx1112basic_stringIcSt11char_traitsIcESaIcEE6appendEPKc")
+      .unwrap()
+      .demangle()
+      .unwrap();
+    assert_eq!(normalize(&demangled), "std::string::append(char const*)");
+
+    let demangled = cpp_demangle::Symbol::new(
+      "_ZN9graphicst5addstERKNSt3__112basic_stringIcNS0_11char_traitsIcEENS0_9allocatorIcEEEE13justificationi",
+    )
+    .unwrap()
+    .demangle()
+    .unwrap();
+    assert_eq!(normalize(&demangled), "graphicst::addst(std::string const&, justification, int)");
+    assert_eq!(split("enabler"), ("enabler", None));
+
+    let demangled = cpp_demangle::Symbol::new("_ZNSs6appendEPKc").unwrap().demangle().unwrap();
+    assert!(copy_on_write(&demangled));
+  }
+
+  #[test]
+  fn std_string_hooks_are_found_in_libstdcxx() {
+    let library = dlopen2::raw::Library::open("libstdc++.so.6").unwrap();
+    let (path, base) = module("libstdc++.so.6").unwrap();
+    let data = std::fs::read(path).unwrap();
+    let wanted: Vec<&Wanted> = TABLE.iter().filter(|w| w.hook.starts_with("std_string_")).collect();
+    let found = find(&data, base, &wanted).unwrap();
+    for (hook, symbol) in [
+      ("std_string_append", "_ZNSt7__cxx1112basic_stringIcSt11char_traitsIcESaIcEE6appendEPKc"),
+      ("std_string_assign", "_ZNSt7__cxx1112basic_stringIcSt11char_traitsIcESaIcEE6assignEPKc"),
+    ] {
+      let (_, address) = found[hook].as_ref().unwrap();
+      assert_eq!(*address, unsafe { library.symbol::<*const u8>(symbol).unwrap() } as usize, "{hook}");
+    }
+  }
+
+  #[test]
+  fn symbols_are_found_in_the_running_executable() {
+    let wanted = wanted("sign", "self", "super_secret_dfint_sign", None);
+    let (path, base) = module("self").unwrap();
+    let data = std::fs::read(path).unwrap();
+    let found = find(&data, base, &[&wanted]).unwrap();
+    let (signature, address) = found["sign"].as_ref().unwrap();
+    assert_eq!(signature, "super_secret_dfint_sign");
+    assert_eq!(*address, crate::super_secret_dfint_sign as usize);
+
+    let missing = self::wanted("missing", "self", "graphicst::addst", None);
+    assert!(find(&data, base, &[&missing]).unwrap()["missing"].is_err());
+  }
+}
@@ -0,0 +1,262 @@
+use anyhow::{anyhow, Result};
+use regex::bytes::{Regex, RegexSet};
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::dictionary::Translation;
+
+// Dictionary entries with typed placeholders in the key, e.g.
+//   "{word:1} has been struck down","{1} ..."
+//   "{number:1} days ago","... {1} ..."
+// captures are referenced by number in the translation, so they can be reordered freely
+
+// bytes that end a word: ascii whitespace and punctuation, except for ' and -
+const WORD: &str = r"[^\x00-\x20\x21-\x26\x28-\x2c\x2e\x2f\x3a-\x40\x5b-\x60\x7b-\x7f]+";
+const NUMBER: &str = r"-?[0-9]+(?:[.,][0-9]+)*";
+const TEXT: &str = r".+?";
+
+enum Part {
+  Literal(Vec<u8>),
+  Capture(usize),
+}
+
+struct Template {
+  source: (Vec<u8>, Vec<u8>),
+  regex: Regex,
+  // placeholder number for every regex group, in order of appearance in the key
+  groups: Vec<usize>,
+  output: Vec<Part>,
+  specificity: usize,
+}
+
+#[derive(Default)]
+pub struct Templates {
+  set: Option<RegexSet>,
+  templates: Vec<Template>,
+  // key and translation of every compiled template, in the same order
+  sources: Vec<(Vec<u8>, Vec<u8>)>,
+  cache: Cache<Option<Arc<Translation>>>,
+}
+
+fn placeholder(s: &[u8]) -> Option<(&'static str, usize, usize)> {
+  let end = s.iter().position(|&c| c == b'}')?;
+  let inner = std::str::from_utf8(&s[1..end]).ok()?;
+  let (kind, index) = inner.split_once(':')?;
+  let pattern = match kind {
+    "number" => NUMBER,
+    "word" => WORD,
+    "text" => TEXT,
+    _ => return None,
+  };
+  Some((pattern, index.parse::<usize>().ok()?, end + 1))
+}
+
+fn reference(s: &[u8]) -> Option<(usize, usize)> {
+  let end = s.iter().position(|&c| c == b'}')?;
+  let index = std::str::from_utf8(&s[1..end]).ok()?.parse::<usize>().ok()?;
+  Some((index, end + 1))
+}
+
+pub fn is_template(key: &[u8]) -> bool {
+  (0..key.len()).any(|i| key[i] == b'{' && placeholder(&key[i..]).is_some())
+}
+
+impl Template {
+  fn new(key: &[u8], value: &[u8]) -> Result<Self> {
+    let mut pattern = String::from("(?s-u)^");
+    let mut groups = Vec::new();
+    let mut specificity = 0;
+    let mut i = 0;
+    while i < key.len() {
+      if key[i] == b'{' {
+        if let Some((kind, index, len)) = placeholder(&key[i..]) {
+          if groups.contains(&index) {
+            return Err(anyhow!("placeholder {index} is used twice"));
+          }
+          groups.push(index);
+          pattern.push_str(&format!("({kind})"));
+          i += len;
+          continue;
+        }
+      }
+      specificity += 1;
+      pattern.push_str(&format!(r"\x{:02x}", key[i]));
+      i += 1;
+    }
+    pattern.push('$');
+
+    let mut output = Vec::new();
+    let mut literal = Vec::new();
+    let mut i = 0;
+    while i < value.len() {
+      if value[i] == b'{' {
+        if let Some((index, len)) = reference(&value[i..]) {
+          if !groups.contains(&index) {
+            return Err(anyhow!("translation uses placeholder {index} which the key does not have"));
+          }
+          if !literal.is_empty() {
+            output.push(Part::Literal(std::mem::take(&mut literal)));
+          }
+          output.push(Part::Capture(index));
+          i += len;
+          continue;
+        }
+      }
+      literal.push(value[i]);
+      i += 1;
+    }
+    if !literal.is_empty() {
+      output.push(Part::Literal(literal));
+    }
+
+    Ok(Self {
+      source: (key.to_vec(), value.to_vec()),
+      regex: Regex::new(&pattern)?,
+      groups,
+      output,
+      specificity,
+    })
+  }
+
+  fn render(&self, key: &[u8]) -> Option<Vec<u8>> {
+    let captures = self.regex.captures(key)?;
+    let mut out = Vec::new();
+    for part in &self.output {
+      match part {
+        Part::Literal(literal) => out.extend_from_slice(literal),
+        Part::Capture(index) => {
+          let group = self.groups.iter().position(|g| g == index)? + 1;
+          out.extend_from_slice(captures.get(group)?.as_bytes());
+        }
+      }
+    }
+    Some(out)
+  }
+}
+
+impl Templates {
+  // entries are (location, key, value), returns the locations that could not be compiled
+  pub fn new<L>(entries: Vec<(L, Vec<u8>, Vec<u8>)>) -> (Self, Vec<(L, String)>) {
+    let mut errors = Vec::new();
+    let mut templates = Vec::new();
+    for (at, key, value) in entries {
+      match Template::new(&key, &value) {
+        Ok(template) => templates.push(template),
+        Err(err) => errors.push((at, format!("{err}"))),
+      }
+    }
+    // the most specific template wins when several match, the stable sort keeps file order for ties
+    templates.sort_by(|a, b| b.specificity.cmp(&a.specificity));
+    let set = match RegexSet::new(templates.iter().map(|t| t.regex.as_str())) {
+      Ok(set) => Some(set),
+      Err(err) => {
+        log::error!("unable to build template set, {err}");
+        templates.clear();
+        None
+      }
+    };
+    let sources = templates.iter().map(|t| t.source.clone()).collect();
+    (
+      Self {
+        set,
+        templates,
+        sources,
+        cache: Cache::default(),
+      },
+      errors,
+    )
+  }
+
+  pub fn len(&self) -> usize {
+    self.templates.len()
+  }
+
+  pub fn sources(&self) -> &[(Vec<u8>, Vec<u8>)] {
+    &self.sources
+  }
+
+  pub fn lookup(&self, key: &[u8]) -> Option<Arc<Translation>> {
+    let set = self.set.as_ref()?;
+    self.cache.get_or_insert_with(key, || {
+      set
+        .matches(key)
+        .iter()
+        .find_map(|i| self.templates[i].render(key))
+        .map(|value| Arc::new(Translation::new(&value)))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn templates(entries: &[(&[u8], &[u8])]) -> Templates {
+    let (templates, errors) = Templates::new(
+      entries
+        .iter()
+        .enumerate()
+        .map(|(i, (k, v))| (i + 1, k.to_vec(), v.to_vec()))
+        .collect(),
+    );
+    assert!(errors.is_empty());
+    templates
+  }
+
+  fn lookup(templates: &Templates, key: &[u8]) -> Option<Vec<u8>> {
+    templates.lookup(key).map(|t| t.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn typed_placeholders() {
+    let t = templates(&[
+      (b"{number:1} days ago", b"N={1}"),
+      (b"{word:1} has been struck down", b"W={1}"),
+      (b"The {text:1} is here", b"T={1}"),
+    ]);
+    assert_eq!(lookup(&t, b"12 days ago"), Some(b"N=12".to_vec()));
+    assert_eq!(lookup(&t, b"many days ago"), None);
+    assert_eq!(lookup(&t, b"Urist has been struck down"), Some(b"W=Urist".to_vec()));
+    assert_eq!(lookup(&t, b"Urist McMiner has been struck down"), None);
+    assert_eq!(lookup(&t, b"The large copper cage is here"), Some(b"T=large copper cage".to_vec()));
+  }
+
+  #[test]
+  fn captures_can_be_reordered() {
+    let t = templates(&[(b"{word:1} gives {number:2} coins to {word:3}", b"{3} <- {2} <- {1}")]);
+    assert_eq!(lookup(&t, b"Urist gives 15 coins to Dodok"), Some(b"Dodok <- 15 <- Urist".to_vec()));
+  }
+
+  #[test]
+  fn most_specific_template_wins() {
+    let t = templates(&[(b"{text:1} ago", b"generic"), (b"{number:1} days ago", b"days {1}")]);
+    assert_eq!(lookup(&t, b"3 days ago"), Some(b"days 3".to_vec()));
+    assert_eq!(lookup(&t, b"a while ago"), Some(b"generic".to_vec()));
+  }
+
+  #[test]
+  fn invalid_templates_are_reported() {
+    let (t, errors) = Templates::new(vec![
+      (3, b"{number:1} days".to_vec(), b"{2}".to_vec()),
+      (7, b"{word:1} and {word:1}".to_vec(), b"{1}".to_vec()),
+    ]);
+    assert_eq!(t.len(), 0);
+    assert_eq!(errors.iter().map(|e| e.0).collect::<Vec<_>>(), vec![3, 7]);
+  }
+
+  #[test]
+  fn misses_are_cached() {
+    let t = templates(&[(b"{number:1} days ago", b"{1}")]);
+    assert_eq!(lookup(&t, b"Stone"), None);
+    assert_eq!(lookup(&t, b"Stone"), None);
+    assert_eq!(lookup(&t, b"3 days ago"), Some(b"3".to_vec()));
+    assert_eq!(t.cache.len(), 2);
+  }
+
+  #[test]
+  fn plain_keys_are_not_templates() {
+    assert!(!is_template(b"Stone"));
+    assert!(!is_template(b"{curly} braces"));
+    assert!(is_template(b"{number:1} days ago"));
+  }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use crate::bidi;
+use crate::capture;
+use crate::config::CONFIG;
+use crate::crash;
+use crate::dictionary::{Dictionary, Found, Translation, DICTIONARY};
+
+// What every translating hook does with a string, as a chain of stages. Each stage gets what the earlier ones
+// found and passes it on, replaced or untouched, so lookups, rewrites of the result and bookkeeping are all
+// stages. Hooks only build the game's string from the result and call the original.
+pub trait Stage: Send + Sync {
+  fn run<'a>(
+    &self,
+    dictionary: &'a Dictionary,
+    key: &[u8],
+    context: &str,
+    found: Option<Found<'a>>,
+  ) -> Option<Found<'a>>;
+}
+
+// the entry for this hook or the context-free one
+pub struct Exact;
+
+impl Stage for Exact {
+  fn run<'a>(
+    &self,
+    dictionary: &'a Dictionary,
+    key: &[u8],
+    context: &str,
+    found: Option<Found<'a>>,
+  ) -> Option<Found<'a>> {
+    found.or_else(|| dictionary.get(key, context))
+  }
+}
+
+// templates are only tried when nothing matched exactly
+pub struct Template;
+
+impl Stage for Template {
+  fn run<'a>(&self, dictionary: &'a Dictionary, key: &[u8], _: &str, found: Option<Found<'a>>) -> Option<Found<'a>> {
+    found.or_else(|| dictionary.template(key))
+  }
+}
+
+// right-to-left translations in the order DF draws them, see bidi.rs
+pub struct Visual {
+  reorder: fn(&[u8]) -> Option<Arc<Translation>>,
+}
+
+impl Visual {
+  // the hooks that draw their string; the others hand strings to DF, which still joins, wraps and compares them
+  // in logical order
+  const CONTEXTS: &'static [&'static str] = &["addst", "addst_top", "addst_flag"];
+}
+
+impl Stage for Visual {
+  fn run<'a>(&self, _: &'a Dictionary, _: &[u8], context: &str, found: Option<Found<'a>>) -> Option<Found<'a>> {
+    let found = found?;
+    if !Self::CONTEXTS.contains(&context) {
+      return Some(found);
+    }
+    match (self.reorder)(found.as_bytes()) {
+      Some(visual) => Some(Found::Owned(visual)),
+      None => Some(found),
+    }
+  }
+}
+
+// remembers what was translated lately, for crash reports
+pub struct Recent {
+  record: fn(&str, &[u8]),
+}
+
+impl Stage for Recent {
+  fn run<'a>(&self, _: &'a Dictionary, key: &[u8], context: &str, found: Option<Found<'a>>) -> Option<Found<'a>> {
+    if found.is_some() {
+      (self.record)(context, key);
+    }
+    found
+  }
+}
+
+// hands strings nothing translated to the untranslated capture
+pub struct Capture {
+  record: fn(&str, &[u8]),
+}
+
+impl Stage for Capture {
+  fn run<'a>(&self, _: &'a Dictionary, key: &[u8], context: &str, found: Option<Found<'a>>) -> Option<Found<'a>> {
+    if found.is_none() {
+      (self.record)(context, key);
+    }
+    found
+  }
+}
+
+#[static_init::dynamic(lazy)]
+pub static TRANSLATOR: Translator = {
+  let mut stages: Vec<Box<dyn Stage>> = vec![Box::new(Exact), Box::new(Template)];
+  if CONFIG.settings.enable_bidi {
+    stages.push(Box::new(Visual { reorder: bidi::visual }));
+  }
+  stages.push(Box::new(Recent {
+    record: crash::remember,
+  }));
+  stages.push(Box::new(Capture {
+    record: capture::record,
+  }));
+  Translator::new(stages)
+};
+
+pub struct Translator {
+  stages: Vec<Box<dyn Stage>>,
+}
+
+impl Translator {
+  pub fn new(stages: Vec<Box<dyn Stage>>) -> Self {
+    Self { stages }
+  }
+
+  // context is the name of the hook the string came through
+  pub fn translate<'a>(&self, dictionary: &'a Dictionary, key: &[u8], context: &str) -> Option<Found<'a>> {
+    self.stages.iter().fold(None, |found, stage| stage.run(dictionary, key, context, found))
+  }
+
+  // runs f with the translation while the current dictionary is held, None keeps the original string
+  pub fn apply<R>(&self, key: &[u8], context: &str, f: impl FnOnce(Option<Found>) -> R) -> R {
+    let dictionary = DICTIONARY.load();
+    f(self.translate(&dictionary, key, context))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+
+  thread_local! {
+    static RECORDED: RefCell<Vec<(String, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+  }
+
+  fn record(context: &str, key: &[u8]) {
+    RECORDED.with(|r| r.borrow_mut().push((String::from(context), key.to_vec())));
+  }
+
+  fn translator() -> Translator {
+    Translator::new(vec![Box::new(Exact), Box::new(Template), Box::new(Capture { record })])
+  }
+
+  #[test]
+  fn stages_run_in_order() {
+    let dictionary = Dictionary::from_entries(
+      &[
+        (b"Stone", "", b"rock"),
+        (b"Stone", "addst", b"label"),
+        (b"3 days", "", b"exact"),
+      ],
+      &[(b"{number:1} days", b"{1} d")],
+    );
+    let translator = translator();
+    let translate = |key: &[u8], context: &str| {
+      translator.translate(&dictionary, key, context).map(|found| found.as_bytes().to_vec())
+    };
+
+    assert_eq!(translate(b"Stone", "addst"), Some(b"label".to_vec()));
+    assert_eq!(translate(b"Stone", "string_copy_n"), Some(b"rock".to_vec()));
+    assert_eq!(translate(b"3 days", "addst"), Some(b"exact".to_vec()));
+    assert_eq!(translate(b"12 days", "addst"), Some(b"12 d".to_vec()));
+    assert_eq!(translate(b"Bar", "addst_top"), None);
+    assert_eq!(
+      RECORDED.with(|r| r.take()),
+      vec![(String::from("addst_top"), b"Bar".to_vec())]
+    );
+  }
+
+  #[test]
+  fn later_stages_can_replace_the_result() {
+    struct Upper;
+
+    impl Stage for Upper {
+      fn run<'a>(&self, _: &'a Dictionary, _: &[u8], _: &str, found: Option<Found<'a>>) -> Option<Found<'a>> {
+        found.map(|found| Found::Owned(Arc::new(Translation::new(&found.as_bytes().to_ascii_uppercase()))))
+      }
+    }
+
+    let dictionary = Dictionary::from_entries(&[(b"Stone", "", b"rock")], &[]);
+    let translator = Translator::new(vec![Box::new(Exact), Box::new(Upper)]);
+    assert_eq!(translator.translate(&dictionary, b"Stone", "").unwrap().as_bytes(), b"ROCK");
+    assert!(translator.translate(&dictionary, b"Bar", "").is_none());
+  }
+
+  #[test]
+  fn visual_order_replaces_the_translation() {
+    fn reverse(logical: &[u8]) -> Option<Arc<Translation>> {
+      let visual: Vec<u8> = logical.iter().rev().copied().collect();
+      (logical.first() == Some(&b'@')).then(|| Arc::new(Translation::new(&visual)))
+    }
+
+    let dictionary = Dictionary::from_entries(&[(b"Stone", "", b"@rock"), (b"Wood", "", b"log")], &[]);
+    let translator = Translator::new(vec![Box::new(Exact), Box::new(Visual { reorder: reverse })]);
+    let translate = |key: &[u8], context: &str| {
+      translator.translate(&dictionary, key, context).map(|found| found.as_bytes().to_vec())
+    };
+    assert_eq!(translate(b"Stone", "addst"), Some(b"kcor@".to_vec()));
+    assert_eq!(translate(b"Stone", "addst_flag"), Some(b"kcor@".to_vec()));
+    assert_eq!(translate(b"Wood", "addst_top"), Some(b"log".to_vec()));
+    assert_eq!(translate(b"Bar", "addst"), None);
+    // strings DF still works with stay in logical order
+    assert_eq!(translate(b"Stone", "string_copy_n"), Some(b"@rock".to_vec()));
+    assert_eq!(translate(b"Stone", "std_string_assign"), Some(b"@rock".to_vec()));
+    assert_eq!(translate(b"Stone", "addst_like"), Some(b"@rock".to_vec()));
+  }
+}
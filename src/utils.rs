@@ -10,11 +10,11 @@ pub static MODULE: usize = unsafe { winapi::um::libloaderapi::GetModuleHandleW(s
 #[static_init::dynamic]
 pub static MODULE: usize = 0;
 
-#[static_init::dynamic]
+#[static_init::dynamic(lazy)]
 static SDL_MESSAGE_BOX: fn(u32, *const i8, *const i8, *const u8) -> i32 =
   unsafe { symbol_handle::<fn(u32, *const i8, *const i8, *const u8) -> i32>(PATH_SDL2, "SDL_ShowSimpleMessageBox") };
 
-#[static_init::dynamic]
+#[static_init::dynamic(lazy)]
 static SDL_ERROR: fn() -> *const i8 = unsafe { symbol_handle::<fn() -> *const i8>(PATH_SDL2, "SDL_GetError") };
 
 pub unsafe fn symbol_handle<T>(module: &str, symbol: &str) -> T {